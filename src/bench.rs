@@ -0,0 +1,83 @@
+use core::arch::x86_64::{_mm_lfence, _rdtsc};
+use core::fmt;
+
+const MAX_SAMPLES: usize = 256;
+const WARMUP_ITERATIONS: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BenchReport {
+    pub name: &'static str,
+    pub iterations: usize,
+    pub min: u64,
+    pub median: u64,
+    pub max: u64,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bench {}: {} iterations, min {} / median {} / max {} cycles",
+            self.name, self.iterations, self.min, self.median, self.max)
+    }
+}
+
+pub fn rdtsc() -> u64 {
+    unsafe {
+        _mm_lfence();
+        let tsc = _rdtsc();
+        _mm_lfence();
+        tsc
+    }
+}
+
+// Iterations are split into at most MAX_SAMPLES equally sized batches, and
+// each sample is the per-iteration cycle count of one batch.
+pub fn run<F: FnMut()>(name: &'static str, iterations: usize, mut f: F) -> BenchReport {
+    use x86_64::instructions::interrupts;
+
+    let iterations = iterations.max(1);
+    let samples = iterations.min(MAX_SAMPLES);
+    let batch = iterations / samples;
+
+    interrupts::without_interrupts(|| {
+        for _ in 0..WARMUP_ITERATIONS {
+            f();
+        }
+
+        let mut cycles = [0u64; MAX_SAMPLES];
+        for sample in cycles.iter_mut().take(samples) {
+            let start = rdtsc();
+            for _ in 0..batch {
+                f();
+            }
+            *sample = (rdtsc() - start) / batch as u64;
+        }
+
+        let cycles = &mut cycles[..samples];
+        cycles.sort_unstable();
+        BenchReport {
+            name,
+            iterations: samples * batch,
+            min: cycles[0],
+            median: cycles[samples / 2],
+            max: cycles[samples - 1],
+        }
+    })
+}
+
+#[macro_export]
+macro_rules! bench {
+    ($name:expr, $iterations:expr, $body:expr) => {{
+        let report = $crate::bench::run($name, $iterations, $body);
+        $crate::serial_println!("{}", report);
+        report
+    }};
+}
+
+#[test_case]
+fn bench_rdtsc_overhead() {
+    let report = bench!("rdtsc", 1000, || {
+        core::hint::black_box(rdtsc());
+    });
+    assert_eq!(report.iterations, 1000 / MAX_SAMPLES * MAX_SAMPLES);
+    assert!(report.min <= report.median && report.median <= report.max);
+}
@@ -0,0 +1,197 @@
+use crate::device::{self, Device, DeviceClass, DeviceStatus};
+use crate::error::KernelResult;
+use crate::log::{self, Level};
+use crate::println;
+use crate::vga_buffer::{self, BUFFER_WIDTH};
+use core::fmt::{self, Write};
+
+// Cells between the brackets of the progress bar.
+pub const BAR_WIDTH: usize = 40;
+
+// Number of filled bar cells after `stage` of `total` stages.
+pub fn filled(stage: usize, total: usize, width: usize) -> usize {
+    if total == 0 {
+        return width;
+    }
+    width * stage.min(total) / total
+}
+
+struct StatusLine {
+    buf: [u8; BUFFER_WIDTH],
+    len: usize,
+}
+
+impl StatusLine {
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for StatusLine {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Anything past the screen width is cut off, at a char boundary.
+        for c in s.chars() {
+            let end = self.len + c.len_utf8();
+            if end > self.buf.len() {
+                break;
+            }
+            c.encode_utf8(&mut self.buf[self.len..end]);
+            self.len = end;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Firmware {
+    Bios,
+    Uefi,
+}
+
+impl fmt::Display for Firmware {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Firmware::Bios => "bios",
+            Firmware::Uefi => "uefi",
+        })
+    }
+}
+
+// How the machine was booted. The bootloader crate (0.9) only has a BIOS
+// stage and its `BootInfo` carries no UEFI system table or framebuffer, so
+// this is always `Bios` for now. Code that depends on it (ACPI discovery, a
+// framebuffer) should still ask, so adding a UEFI path only changes this.
+pub fn firmware() -> Firmware {
+    Firmware::Bios
+}
+
+pub struct Driver {
+    pub name: &'static str,
+    pub class: DeviceClass,
+    pub init: fn() -> KernelResult<()>,
+    // Not needed for the console; safe mode leaves it out.
+    pub optional: bool,
+}
+
+// Brings the drivers up in order. A failure is printed and registered, and
+// the remaining drivers still run, so a missing or broken device degrades
+// the boot instead of stopping it. In safe mode optional drivers are logged
+// and skipped. Returns how many drivers came up.
+pub fn init_drivers(drivers: &[Driver], safe_mode: bool) -> usize {
+    let mut ready = 0;
+    for driver in drivers {
+        if safe_mode && driver.optional {
+            log::kv!(Level::Warn, "safe mode: skipping optional driver", name = driver.name);
+            continue;
+        }
+        let result = (driver.init)();
+        match result {
+            Ok(()) => ready += 1,
+            Err(err) => println!("[failed] {}: {}", driver.name, err),
+        }
+        device::register(Device {
+            name: driver.name,
+            class: driver.class,
+            status: DeviceStatus::from_result(&result),
+        });
+    }
+    ready
+}
+
+// Shows boot progress on the VGA status row, e.g.
+// `[##########..............................]  25% interrupts`.
+// There is no framebuffer, so the bar is drawn in text mode. It costs one
+// status row redraw per stage.
+pub fn progress(stage: usize, total: usize, label: &str) {
+    let fill = filled(stage, total, BAR_WIDTH);
+    let mut line = StatusLine { buf: [0; BUFFER_WIDTH], len: 0 };
+    let _ = write!(line, "[{:#<fill$}{:.<rest$}] {:>3}% {}", "", "",
+        filled(stage, total, 100), label, fill = fill, rest = BAR_WIDTH - fill);
+    vga_buffer::set_status(line.as_str());
+}
+
+#[test_case]
+fn test_fill_matches_fraction() {
+    assert_eq!(filled(0, 4, BAR_WIDTH), 0);
+    assert_eq!(filled(1, 4, BAR_WIDTH), 10);
+    assert_eq!(filled(3, 4, BAR_WIDTH), 30);
+    assert_eq!(filled(4, 4, BAR_WIDTH), BAR_WIDTH);
+    assert_eq!(filled(5, 4, BAR_WIDTH), BAR_WIDTH);
+    assert_eq!(filled(0, 0, BAR_WIDTH), BAR_WIDTH);
+}
+
+#[test_case]
+fn test_bootloader_boots_via_bios() {
+    assert_eq!(firmware(), Firmware::Bios);
+}
+
+#[test_case]
+fn test_progress_draws_bar() {
+    use crate::vga_buffer::{STATUS_ROW, WRITER};
+    use x86_64::instructions::interrupts;
+
+    for stage in 0..=3 {
+        progress(stage, 3, "stage");
+        let hashes = interrupts::without_interrupts(|| {
+            let writer = WRITER.lock();
+            (1..=BAR_WIDTH)
+                .filter(|&col| writer.read_at(STATUS_ROW, col).unwrap().0 == b'#')
+                .count()
+        });
+        assert_eq!(hashes, filled(stage, 3, BAR_WIDTH));
+    }
+}
+
+#[test_case]
+fn test_missing_device_does_not_stop_boot() {
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use crate::error::KernelError;
+
+    static LATER_DRIVER_RAN: AtomicBool = AtomicBool::new(false);
+
+    let drivers = [
+        Driver {
+            name: "mock-not-found",
+            class: DeviceClass::Input,
+            init: || Err(KernelError::DeviceNotFound),
+            optional: false,
+        },
+        Driver {
+            name: "mock-later",
+            class: DeviceClass::Input,
+            init: || {
+                LATER_DRIVER_RAN.store(true, Ordering::Relaxed);
+                Ok(())
+            },
+            optional: false,
+        },
+    ];
+    assert_eq!(init_drivers(&drivers, false), 1);
+    assert!(LATER_DRIVER_RAN.load(Ordering::Relaxed));
+
+    let status = |name| device::list().find(|device| device.name == name).map(|device| device.status);
+    assert_eq!(status("mock-not-found"), Some(DeviceStatus::Absent));
+    assert_eq!(status("mock-later"), Some(DeviceStatus::Ok));
+}
+
+#[test_case]
+fn test_safe_mode_skips_optional_drivers() {
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    static RUNS: AtomicU32 = AtomicU32::new(0);
+
+    fn count_run() -> KernelResult<()> {
+        RUNS.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    let drivers = [
+        Driver { name: "mock-optional", class: DeviceClass::Input, init: count_run, optional: true },
+        Driver { name: "mock-required", class: DeviceClass::Input, init: count_run, optional: false },
+    ];
+    assert_eq!(init_drivers(&drivers, true), 1);
+    assert_eq!(RUNS.load(Ordering::Relaxed), 1);
+    assert!(device::list().all(|device| device.name != "mock-optional"));
+    assert_eq!(init_drivers(&drivers, false), 2);
+    assert_eq!(RUNS.load(Ordering::Relaxed), 3);
+}
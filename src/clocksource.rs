@@ -0,0 +1,125 @@
+use crate::bench::rdtsc;
+use crate::log::{self, Level};
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use x86_64::instructions::port::Port;
+
+// The PIT input clock; left unprogrammed, it divides this by 65536.
+const PIT_HZ: u64 = 1_193_182;
+const PIT_DIVISOR: u64 = 65_536;
+const CALIBRATION_MS: u64 = 10;
+// Gives up on a PIT that never counts down, far beyond 10 ms on any CPU.
+const CALIBRATION_TIMEOUT: usize = 100_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Source {
+    Pit,
+    InvariantTsc,
+}
+
+static SOURCE: AtomicU8 = AtomicU8::new(Source::Pit as u8);
+static PIT_TICKS: AtomicU64 = AtomicU64::new(0);
+static TSC_KHZ: AtomicU64 = AtomicU64::new(0);
+static TSC_BASE: AtomicU64 = AtomicU64::new(0);
+
+// Called from the timer interrupt.
+pub(crate) fn timer_tick() {
+    PIT_TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+// CPUID.80000007H:EDX[8]: the TSC runs at a constant rate in every P-, C-
+// and T-state, so it can measure wall time.
+pub fn has_invariant_tsc() -> bool {
+    use core::arch::x86_64::__cpuid;
+
+    __cpuid(0x8000_0000).eax >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+// Times CALIBRATION_MS on PIT channel 2 (one-shot, gated through port 0x61
+// with the speaker off) and returns the TSC rate in kHz.
+pub fn calibrate_tsc_khz() -> Option<u64> {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    const GATE: u8 = 1 << 0;
+    const SPEAKER: u8 = 1 << 1;
+    const OUT: u8 = 1 << 5;
+
+    let count = PIT_HZ * CALIBRATION_MS / 1000;
+    without_interrupts(|| {
+        let mut control: Port<u8> = Port::new(0x61);
+        let mut command: Port<u8> = Port::new(0x43);
+        let mut channel_2: Port<u8> = Port::new(0x42);
+        unsafe {
+            let saved = control.read();
+            control.write((saved & !SPEAKER) | GATE);
+            // Channel 2, low then high byte, mode 0 (OUT rises at zero).
+            command.write(0b1011_0000);
+            channel_2.write(count as u8);
+            channel_2.write((count >> 8) as u8);
+            let start = rdtsc();
+            let expired = (0..CALIBRATION_TIMEOUT).any(|_| control.read() & OUT != 0);
+            let cycles = rdtsc() - start;
+            control.write(saved);
+            expired.then_some(cycles / CALIBRATION_MS)
+        }
+    })
+}
+
+// Picks the best source and logs the choice. There is no APIC timer
+// support, so the fallback is the PIT tick count.
+pub fn init() {
+    let khz = if has_invariant_tsc() { calibrate_tsc_khz() } else { None };
+    match khz {
+        Some(khz) => {
+            TSC_KHZ.store(khz, Ordering::Relaxed);
+            TSC_BASE.store(rdtsc(), Ordering::Relaxed);
+            SOURCE.store(Source::InvariantTsc as u8, Ordering::Release);
+            log::kv!(Level::Info, "clocksource", source = "invariant-tsc", tsc_khz = khz);
+        }
+        None => {
+            SOURCE.store(Source::Pit as u8, Ordering::Release);
+            log::kv!(Level::Info, "clocksource", source = "pit", hz = PIT_HZ / PIT_DIVISOR);
+        }
+    }
+}
+
+pub fn source() -> Source {
+    match SOURCE.load(Ordering::Acquire) {
+        1 => Source::InvariantTsc,
+        _ => Source::Pit,
+    }
+}
+
+// Nanoseconds since `init` (TSC) or since the first timer tick (PIT). Both
+// counters only move forward on this single CPU, so this is monotonic; with
+// the PIT it advances in steps of about 55 ms.
+pub fn now_ns() -> u64 {
+    match source() {
+        Source::InvariantTsc => {
+            let cycles = rdtsc().saturating_sub(TSC_BASE.load(Ordering::Relaxed));
+            let khz = TSC_KHZ.load(Ordering::Relaxed);
+            (u128::from(cycles) * 1_000_000 / u128::from(khz)) as u64
+        }
+        Source::Pit => {
+            let ticks = PIT_TICKS.load(Ordering::Relaxed);
+            (u128::from(ticks) * u128::from(PIT_DIVISOR) * 1_000_000_000 / u128::from(PIT_HZ)) as u64
+        }
+    }
+}
+
+#[test_case]
+fn test_now_ns_is_monotonic() {
+    let mut last = now_ns();
+    for _ in 0..100_000 {
+        let now = now_ns();
+        assert!(now >= last);
+        last = now;
+    }
+}
+
+#[test_case]
+fn test_tsc_calibration_is_plausible() {
+    let khz = calibrate_tsc_khz().unwrap();
+    // Anything between 100 MHz and 10 GHz.
+    assert!((100_000..10_000_000).contains(&khz), "TSC at {} kHz", khz);
+}
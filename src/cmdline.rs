@@ -0,0 +1,69 @@
+// The bootloader does not pass a command line, so it is embedded at build
+// time: `MENT_OS_CMDLINE="loglevel=debug quiet" cargo build`.
+const EMBEDDED: &str = match option_env!("MENT_OS_CMDLINE") {
+    Some(cmdline) => cmdline,
+    None => "",
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Cmdline<'a> {
+    raw: &'a str,
+}
+
+impl<'a> Cmdline<'a> {
+    pub const fn new(raw: &'a str) -> Self {
+        Cmdline { raw }
+    }
+
+    pub fn raw(&self) -> &'a str {
+        self.raw
+    }
+
+    pub fn tokens(&self) -> impl Iterator<Item = &'a str> {
+        self.raw.split_ascii_whitespace()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.tokens()
+            .filter_map(|token| token.split_once('='))
+            .filter(|&(k, _)| k == key)
+            .map(|(_, value)| value)
+            .last()
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.tokens().any(|token| token == name)
+    }
+}
+
+static CMDLINE: Cmdline<'static> = Cmdline::new(EMBEDDED);
+
+pub fn cmdline() -> Cmdline<'static> {
+    CMDLINE
+}
+
+pub fn get(key: &str) -> Option<&'static str> {
+    CMDLINE.get(key)
+}
+
+pub fn flag(name: &str) -> bool {
+    CMDLINE.flag(name)
+}
+
+// Parses a decimal or `0x`-prefixed hex number, as used by `randseed=`.
+pub fn parse_u64(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+#[test_case]
+fn test_parse_values_and_flags() {
+    let cmdline = Cmdline::new("loglevel=debug console=serial quiet");
+    assert_eq!(cmdline.get("loglevel"), Some("debug"));
+    assert_eq!(cmdline.get("console"), Some("serial"));
+    assert!(cmdline.flag("quiet"));
+    assert!(!cmdline.flag("loglevel"));
+    assert_eq!(cmdline.get("quiet"), None);
+}
@@ -0,0 +1,122 @@
+use core::fmt;
+use x86_64::instructions::port::Port;
+
+const ADDRESS_PORT: u16 = 0x70;
+const DATA_PORT: u16 = 0x71;
+// Bit 7 of the address port masks NMIs; it is kept set while selecting.
+const NMI_DISABLE: u8 = 1 << 7;
+
+pub const RTC_SECONDS: u8 = 0x00;
+pub const RTC_MINUTES: u8 = 0x02;
+pub const RTC_HOURS: u8 = 0x04;
+pub const RTC_STATUS_A: u8 = 0x0a;
+pub const RTC_STATUS_B: u8 = 0x0b;
+// Outside the RTC block and the 0x10-0x2d range covered by the BIOS
+// checksum; the RTC keeps it across a warm reset.
+const REBOOT_REASON: u8 = 0x50;
+// Marks the byte as ours, so whatever firmware left there is not misread.
+const REBOOT_REASON_MAGIC: u8 = 0xb0;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_BINARY: u8 = 1 << 2;
+
+// Selecting a register and accessing it are two port operations, so they
+// run with interrupts disabled.
+pub fn read(register: u8) -> u8 {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    without_interrupts(|| unsafe {
+        Port::new(ADDRESS_PORT).write(NMI_DISABLE | register);
+        let value = Port::new(DATA_PORT).read();
+        Port::new(ADDRESS_PORT).write(0u8);
+        value
+    })
+}
+
+fn write(register: u8, value: u8) {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    without_interrupts(|| unsafe {
+        Port::new(ADDRESS_PORT).write(NMI_DISABLE | register);
+        Port::new(DATA_PORT).write(value);
+        Port::new(ADDRESS_PORT).write(0u8);
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebootReason {
+    Panic,
+    User,
+    Watchdog,
+}
+
+impl RebootReason {
+    const ALL: [RebootReason; 3] = [RebootReason::Panic, RebootReason::User, RebootReason::Watchdog];
+}
+
+impl fmt::Display for RebootReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RebootReason::Panic => "panic",
+            RebootReason::User => "user request",
+            RebootReason::Watchdog => "watchdog",
+        })
+    }
+}
+
+// Recorded by `reboot` just before it resets the machine.
+pub fn set_reboot_reason(reason: RebootReason) {
+    write(REBOOT_REASON, REBOOT_REASON_MAGIC | reason as u8);
+}
+
+// The reason the previous boot recorded, if any. Clears it, so a later reset
+// that does not go through `reboot` (a triple fault, the reset button) reads
+// as `None`.
+pub fn take_reboot_reason() -> Option<RebootReason> {
+    let value = read(REBOOT_REASON);
+    write(REBOOT_REASON, 0);
+    if value & 0xf0 != REBOOT_REASON_MAGIC {
+        return None;
+    }
+    RebootReason::ALL.get(usize::from(value & 0x0f)).copied()
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0xf)
+}
+
+// The RTC time of day as (hours, minutes, seconds), in 24-hour binary form
+// only when the RTC is set up that way; BCD is converted, 12-hour mode is
+// not. Waits out an update in progress so the fields are consistent.
+pub fn rtc_time() -> (u8, u8, u8) {
+    while read(RTC_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    let (hours, minutes, seconds) = (read(RTC_HOURS), read(RTC_MINUTES), read(RTC_SECONDS));
+    if read(RTC_STATUS_B) & STATUS_B_BINARY != 0 {
+        (hours, minutes, seconds)
+    } else {
+        (from_bcd(hours), from_bcd(minutes), from_bcd(seconds))
+    }
+}
+
+#[test_case]
+fn test_rtc_time_is_valid() {
+    assert_eq!(from_bcd(0x59), 59);
+    let (hours, minutes, seconds) = rtc_time();
+    assert!(hours < 24 && minutes < 60 && seconds < 60);
+}
+
+#[test_case]
+fn test_reboot_reason_round_trips() {
+    let (_, _, seconds) = rtc_time();
+    set_reboot_reason(RebootReason::Watchdog);
+    assert_eq!(take_reboot_reason(), Some(RebootReason::Watchdog));
+    assert_eq!(take_reboot_reason(), None);
+    write(REBOOT_REASON, 0x42);
+    assert_eq!(take_reboot_reason(), None);
+    // The RTC is untouched; it may have ticked once meanwhile, possibly
+    // into the next minute.
+    let (_, _, now) = rtc_time();
+    assert!((now + 60 - seconds) % 60 <= 1, "RTC seconds went from {} to {}", seconds, now);
+}
@@ -0,0 +1,149 @@
+use crate::bench::rdtsc;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+// Length of one utilization window in TSC cycles (a fraction of a second on
+// anything QEMU runs on).
+const WINDOW_CYCLES: u64 = 200_000_000;
+
+struct Usage {
+    window_start: u64,
+    idle: u64,
+    // Start of the current sleep not yet credited to `idle`.
+    sleeping_since: Option<u64>,
+    last: u8,
+}
+
+impl Usage {
+    fn credit_sleep(&mut self, now: u64) {
+        if let Some(since) = self.sleeping_since {
+            self.idle += now.saturating_sub(since.max(self.window_start));
+            self.sleeping_since = Some(now);
+        }
+    }
+
+    // Closes the current window once it is long enough and starts a new one.
+    fn roll(&mut self, now: u64) {
+        let elapsed = now.wrapping_sub(self.window_start);
+        if elapsed < WINDOW_CYCLES {
+            return;
+        }
+        self.credit_sleep(now);
+        let busy = elapsed.saturating_sub(self.idle);
+        self.last = (busy * 100 / elapsed) as u8;
+        self.window_start = now;
+        self.idle = 0;
+    }
+}
+
+lazy_static! {
+    static ref USAGE: Mutex<Usage> = Mutex::new(Usage {
+        window_start: rdtsc(),
+        idle: 0,
+        sleeping_since: None,
+        last: 0,
+    });
+}
+
+// Sleeps until the next interrupt and counts the time as idle. Like
+// `enable_and_hlt`, it must be called with interrupts disabled and returns
+// with them enabled, so a wakeup checked for just before cannot be missed.
+//
+// The interrupt that ends the sleep is handled before `hlt` returns, so its
+// handler counts as idle time too. Handlers are short, so the error is small.
+pub fn idle() {
+    use x86_64::instructions::interrupts;
+
+    USAGE.lock().sleeping_since = Some(rdtsc());
+    interrupts::enable_and_hlt();
+    interrupts::disable();
+    {
+        let mut usage = USAGE.lock();
+        let now = rdtsc();
+        usage.credit_sleep(now);
+        usage.sleeping_since = None;
+        usage.roll(now);
+    }
+    interrupts::enable();
+}
+
+// Called from the timer interrupt so windows close on time even while
+// nothing sleeps or asks for the usage.
+pub(crate) fn timer_tick() {
+    USAGE.lock().roll(rdtsc());
+}
+
+// Busy percentage of the last complete window, 0 until one has completed.
+pub fn usage() -> u8 {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut usage = USAGE.lock();
+        usage.roll(rdtsc());
+        usage.last
+    })
+}
+
+const IA32_THERM_STATUS: u32 = 0x19c;
+const IA32_TEMPERATURE_TARGET: u32 = 0x1a2;
+
+fn is_intel() -> bool {
+    let vendor = core::arch::x86_64::__cpuid(0);
+    (vendor.ebx, vendor.edx, vendor.ecx) == (0x756e_6547, 0x4965_6e69, 0x6c65_746e)
+}
+
+// Core temperature in degrees Celsius from the digital thermal sensor, or
+// None if there is none. The sensor reports how far below TjMax (the
+// throttling temperature) the core is, so the reading is TjMax minus that
+// offset. Both MSRs are Intel-only and fault elsewhere, so the vendor and
+// CPUID.06H:EAX[0] are checked first.
+pub fn temperature() -> Option<u32> {
+    use core::arch::x86_64::__cpuid;
+    use x86_64::registers::model_specific::Msr;
+
+    if !is_intel() || __cpuid(0).eax < 6 {
+        return None;
+    }
+    if __cpuid(6).eax & 1 == 0 {
+        return None;
+    }
+    let (status, target) = unsafe {
+        (Msr::new(IA32_THERM_STATUS).read(), Msr::new(IA32_TEMPERATURE_TARGET).read())
+    };
+    // Bit 31 says whether the readout in bits 22:16 is valid.
+    if status & (1 << 31) == 0 {
+        return None;
+    }
+    let offset = ((status >> 16) & 0x7f) as u32;
+    let tjmax = ((target >> 16) & 0xff) as u32;
+    tjmax.checked_sub(offset)
+}
+
+#[test_case]
+fn test_usage_follows_load() {
+    use x86_64::instructions::interrupts;
+
+    // Spends at least two full windows either spinning or halting, so the
+    // last complete window saw only that kind of load.
+    fn run_for_two_windows(busy: bool) {
+        let start = rdtsc();
+        while rdtsc().wrapping_sub(start) < 2 * WINDOW_CYCLES {
+            if busy {
+                core::hint::spin_loop();
+            } else {
+                interrupts::disable();
+                idle();
+            }
+        }
+    }
+
+    run_for_two_windows(true);
+    assert!(usage() >= 90, "busy usage {}%", usage());
+    run_for_two_windows(false);
+    assert!(usage() <= 50, "idle usage {}%", usage());
+}
+
+#[test_case]
+fn test_no_temperature_under_qemu() {
+    assert_eq!(temperature(), None);
+}
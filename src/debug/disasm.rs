@@ -0,0 +1,471 @@
+use core::fmt;
+use x86_64::VirtAddr;
+
+// The longest legal x86 instruction.
+pub const MAX_INSTRUCTION_LEN: usize = 15;
+
+const ALU: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+const SHIFT: [&str; 8] = ["rol", "ror", "rcl", "rcr", "shl", "shr", "sal", "sar"];
+const GROUP3: [&str; 8] = ["test", "test", "not", "neg", "mul", "imul", "div", "idiv"];
+const JCC: [&str; 16] = [
+    "jo", "jno", "jb", "jae", "je", "jne", "jbe", "ja",
+    "js", "jns", "jp", "jnp", "jl", "jge", "jle", "jg",
+];
+
+const REGS_64: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi",
+    "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15",
+];
+const REGS_32: [&str; 16] = [
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi",
+    "r8d", "r9d", "r10d", "r11d", "r12d", "r13d", "r14d", "r15d",
+];
+const REGS_16: [&str; 16] = [
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di",
+    "r8w", "r9w", "r10w", "r11w", "r12w", "r13w", "r14w", "r15w",
+];
+const REGS_8: [&str; 16] = [
+    "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil",
+    "r8b", "r9b", "r10b", "r11b", "r12b", "r13b", "r14b", "r15b",
+];
+// Without a REX prefix, byte registers 4-7 are the high halves.
+const REGS_8_LEGACY: [&str; 4] = ["ah", "ch", "dh", "bh"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Register {
+    number: u8,
+    size: u8,
+    rex: bool,
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let number = usize::from(self.number);
+        let name = match self.size {
+            8 => REGS_64[number],
+            4 => REGS_32[number],
+            2 => REGS_16[number],
+            _ if !self.rex && (4..8).contains(&number) => REGS_8_LEGACY[number - 4],
+            _ => REGS_8[number],
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Base {
+    None,
+    Register(u8),
+    Rip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Memory {
+    base: Base,
+    index: Option<(u8, u8)>,
+    displacement: i32,
+    size: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    None,
+    Register(Register),
+    Memory(Memory),
+    Immediate(i64),
+    // Relative to the end of the instruction.
+    Target(i64),
+}
+
+// One decoded instruction, printed in Intel syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u64,
+    pub len: usize,
+    pub mnemonic: &'static str,
+    operands: [Operand; 2],
+}
+
+impl Instruction {
+    fn next(&self) -> u64 {
+        self.address.wrapping_add(self.len as u64)
+    }
+
+    fn fmt_operand(&self, f: &mut fmt::Formatter, operand: Operand) -> fmt::Result {
+        match operand {
+            Operand::None => Ok(()),
+            Operand::Register(register) => write!(f, "{}", register),
+            Operand::Immediate(value) if value < 0 => write!(f, "-{:#x}", value.unsigned_abs()),
+            Operand::Immediate(value) => write!(f, "{:#x}", value),
+            Operand::Target(offset) => write!(f, "{:#x}", self.next().wrapping_add(offset as u64)),
+            Operand::Memory(memory) => {
+                // The size is implied by a register operand, if there is one.
+                if !self.operands.iter().any(|op| matches!(op, Operand::Register(_))) {
+                    let size = match memory.size {
+                        1 => "byte",
+                        2 => "word",
+                        4 => "dword",
+                        _ => "qword",
+                    };
+                    write!(f, "{} ptr ", size)?;
+                }
+                let mut displacement = i64::from(memory.displacement);
+                f.write_str("[")?;
+                let mut first = true;
+                match memory.base {
+                    Base::None => {}
+                    Base::Register(number) => {
+                        write!(f, "{}", REGS_64[usize::from(number)])?;
+                        first = false;
+                    }
+                    // Shown as the absolute address it refers to.
+                    Base::Rip => {
+                        displacement = self.next().wrapping_add(displacement as u64) as i64;
+                    }
+                }
+                if let Some((index, scale)) = memory.index {
+                    if !first {
+                        f.write_str("+")?;
+                    }
+                    write!(f, "{}*{}", REGS_64[usize::from(index)], scale)?;
+                    first = false;
+                }
+                if first {
+                    write!(f, "{:#x}", displacement as u64)?;
+                } else if displacement < 0 {
+                    write!(f, "-{:#x}", displacement.unsigned_abs())?;
+                } else if displacement > 0 {
+                    write!(f, "+{:#x}", displacement)?;
+                }
+                f.write_str("]")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.mnemonic)?;
+        for (i, &operand) in self.operands.iter().enumerate() {
+            if operand == Operand::None {
+                break;
+            }
+            f.write_str(if i == 0 { " " } else { ", " })?;
+            self.fmt_operand(f, operand)?;
+        }
+        Ok(())
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    rex: u8,
+    operand_size_prefix: bool,
+}
+
+impl Decoder<'_> {
+    fn byte(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn le_bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.bytes.get(self.pos..self.pos + N)?.try_into().ok()?;
+        self.pos += N;
+        Some(bytes)
+    }
+
+    fn imm8(&mut self) -> Option<i64> {
+        Some(i64::from(self.byte()? as i8))
+    }
+
+    fn imm32(&mut self) -> Option<i64> {
+        Some(i64::from(i32::from_le_bytes(self.le_bytes()?)))
+    }
+
+    // An immediate of the operand size; 64-bit operands take a sign-extended
+    // 32-bit immediate.
+    fn imm(&mut self, size: u8) -> Option<i64> {
+        match size {
+            1 => self.imm8(),
+            2 => Some(i64::from(i16::from_le_bytes(self.le_bytes()?))),
+            _ => self.imm32(),
+        }
+    }
+
+    fn rex_w(&self) -> bool {
+        self.rex & 0b1000 != 0
+    }
+
+    fn operand_size(&self) -> u8 {
+        if self.rex_w() {
+            8
+        } else if self.operand_size_prefix {
+            2
+        } else {
+            4
+        }
+    }
+
+    fn register(&self, number: u8, size: u8) -> Operand {
+        Operand::Register(Register { number, size, rex: self.rex != 0 })
+    }
+
+    // Decodes a ModRM byte (and SIB/displacement) into the reg field and the
+    // r/m operand.
+    fn modrm(&mut self, size: u8) -> Option<(u8, Operand)> {
+        let modrm = self.byte()?;
+        let mode = modrm >> 6;
+        let reg = ((modrm >> 3) & 7) | ((self.rex & 0b100) << 1);
+        let rm = modrm & 7;
+        let rex_b = (self.rex & 1) << 3;
+
+        if mode == 3 {
+            return Some((reg, self.register(rm | rex_b, size)));
+        }
+
+        let mut memory = Memory { base: Base::None, index: None, displacement: 0, size };
+        let mut disp32 = mode == 2;
+        if rm == 4 {
+            let sib = self.byte()?;
+            let index = ((sib >> 3) & 7) | ((self.rex & 0b10) << 2);
+            if index != 4 {
+                memory.index = Some((index, 1 << (sib >> 6)));
+            }
+            if sib & 7 == 5 && mode == 0 {
+                disp32 = true;
+            } else {
+                memory.base = Base::Register((sib & 7) | rex_b);
+            }
+        } else if rm == 5 && mode == 0 {
+            memory.base = Base::Rip;
+            disp32 = true;
+        } else {
+            memory.base = Base::Register(rm | rex_b);
+        }
+        if mode == 1 {
+            memory.displacement = i32::from(self.byte()? as i8);
+        } else if disp32 {
+            memory.displacement = i32::from_le_bytes(self.le_bytes()?);
+        }
+        Some((reg, Operand::Memory(memory)))
+    }
+}
+
+// Decodes the instruction at the start of `bytes`, which were read from
+// `address`. Only common general-purpose instructions are known; anything
+// else, or a truncated instruction, gives None.
+pub fn decode(bytes: &[u8], address: u64) -> Option<Instruction> {
+    let mut d = Decoder { bytes, pos: 0, rex: 0, operand_size_prefix: false };
+
+    let mut opcode = d.byte()?;
+    if opcode == 0x66 {
+        d.operand_size_prefix = true;
+        opcode = d.byte()?;
+    }
+    if opcode & 0xf0 == 0x40 {
+        d.rex = opcode;
+        opcode = d.byte()?;
+    }
+
+    let size = d.operand_size();
+    let none = Operand::None;
+    let (mnemonic, operands) = match opcode {
+        // add/or/adc/sbb/and/sub/xor/cmp in their six encodings each.
+        0x00..=0x3f if opcode & 7 < 6 => {
+            let mnemonic = ALU[usize::from(opcode >> 3)];
+            let size = if opcode & 1 == 0 { 1 } else { size };
+            match opcode & 7 {
+                0 | 1 => {
+                    let (reg, rm) = d.modrm(size)?;
+                    (mnemonic, [rm, d.register(reg, size)])
+                }
+                2 | 3 => {
+                    let (reg, rm) = d.modrm(size)?;
+                    (mnemonic, [d.register(reg, size), rm])
+                }
+                _ => (mnemonic, [d.register(0, size), Operand::Immediate(d.imm(size)?)]),
+            }
+        }
+        0x50..=0x57 => ("push", [d.register((opcode & 7) | ((d.rex & 1) << 3), 8), none]),
+        0x58..=0x5f => ("pop", [d.register((opcode & 7) | ((d.rex & 1) << 3), 8), none]),
+        0x63 => {
+            let (reg, rm) = d.modrm(4)?;
+            ("movsxd", [d.register(reg, size), rm])
+        }
+        0x70..=0x7f => (JCC[usize::from(opcode & 0xf)], [Operand::Target(d.imm8()?), none]),
+        0x80 | 0x81 | 0x83 => {
+            let rm_size = if opcode == 0x80 { 1 } else { size };
+            let (reg, rm) = d.modrm(rm_size)?;
+            let imm = if opcode == 0x81 { d.imm(rm_size)? } else { d.imm8()? };
+            (ALU[usize::from(reg & 7)], [rm, Operand::Immediate(imm)])
+        }
+        0x84 | 0x85 | 0x88 | 0x89 => {
+            let size = if opcode & 1 == 0 { 1 } else { size };
+            let (reg, rm) = d.modrm(size)?;
+            let mnemonic = if opcode < 0x88 { "test" } else { "mov" };
+            (mnemonic, [rm, d.register(reg, size)])
+        }
+        0x8a | 0x8b | 0x8d => {
+            let size = if opcode == 0x8a { 1 } else { size };
+            let (reg, rm) = d.modrm(size)?;
+            let mnemonic = if opcode == 0x8d { "lea" } else { "mov" };
+            (mnemonic, [d.register(reg, size), rm])
+        }
+        0x90 => ("nop", [none, none]),
+        0xa8 => ("test", [d.register(0, 1), Operand::Immediate(d.imm8()?)]),
+        0xa9 => ("test", [d.register(0, size), Operand::Immediate(d.imm(size)?)]),
+        0xb0..=0xb7 => {
+            let reg = d.register((opcode & 7) | ((d.rex & 1) << 3), 1);
+            ("mov", [reg, Operand::Immediate(d.imm8()?)])
+        }
+        0xb8..=0xbf => {
+            let reg = d.register((opcode & 7) | ((d.rex & 1) << 3), size);
+            // The only form with a full 64-bit immediate.
+            let imm = if d.rex_w() { i64::from_le_bytes(d.le_bytes()?) } else { d.imm(size)? };
+            ("mov", [reg, Operand::Immediate(imm)])
+        }
+        0xc1 | 0xd1 | 0xd3 => {
+            let (reg, rm) = d.modrm(size)?;
+            let count = match opcode {
+                0xc1 => Operand::Immediate(d.imm8()?),
+                0xd1 => Operand::Immediate(1),
+                _ => d.register(1, 1),
+            };
+            (SHIFT[usize::from(reg & 7)], [rm, count])
+        }
+        0xc3 => ("ret", [none, none]),
+        0xc6 | 0xc7 => {
+            let size = if opcode == 0xc6 { 1 } else { size };
+            let (reg, rm) = d.modrm(size)?;
+            if reg & 7 != 0 {
+                return None;
+            }
+            ("mov", [rm, Operand::Immediate(d.imm(size)?)])
+        }
+        0xc9 => ("leave", [none, none]),
+        0xcc => ("int3", [none, none]),
+        0xcd => ("int", [Operand::Immediate(i64::from(d.byte()?)), none]),
+        0xe8 => ("call", [Operand::Target(d.imm32()?), none]),
+        0xe9 => ("jmp", [Operand::Target(d.imm32()?), none]),
+        0xeb => ("jmp", [Operand::Target(d.imm8()?), none]),
+        0xec => ("in", [d.register(0, 1), d.register(2, 2)]),
+        0xee => ("out", [d.register(2, 2), d.register(0, 1)]),
+        0xf4 => ("hlt", [none, none]),
+        0xf6 | 0xf7 => {
+            let size = if opcode == 0xf6 { 1 } else { size };
+            let (reg, rm) = d.modrm(size)?;
+            let reg = usize::from(reg & 7);
+            let imm = if reg < 2 { Operand::Immediate(d.imm(size)?) } else { none };
+            (GROUP3[reg], [rm, imm])
+        }
+        0xfa => ("cli", [none, none]),
+        0xfb => ("sti", [none, none]),
+        0xfe | 0xff => {
+            let size = if opcode == 0xfe { 1 } else { size };
+            let (reg, rm) = d.modrm(size)?;
+            let mnemonic = match (opcode, reg & 7) {
+                (_, 0) => "inc",
+                (_, 1) => "dec",
+                (0xff, 2) => "call",
+                (0xff, 4) => "jmp",
+                (0xff, 6) => "push",
+                _ => return None,
+            };
+            // Near calls, jumps and pushes are always 64-bit.
+            let rm = match rm {
+                Operand::Register(register) if reg & 7 >= 2 => d.register(register.number, 8),
+                Operand::Memory(memory) if reg & 7 >= 2 => Operand::Memory(Memory { size: 8, ..memory }),
+                rm => rm,
+            };
+            (mnemonic, [rm, none])
+        }
+        0x0f => match d.byte()? {
+            0x05 => ("syscall", [none, none]),
+            0x0b => ("ud2", [none, none]),
+            0x1f => ("nop", [d.modrm(size)?.1, none]),
+            0x31 => ("rdtsc", [none, none]),
+            0x80..=0x8f => {
+                let opcode = bytes[d.pos - 1];
+                (JCC[usize::from(opcode & 0xf)], [Operand::Target(d.imm32()?), none])
+            }
+            0xa2 => ("cpuid", [none, none]),
+            0xaf => {
+                let (reg, rm) = d.modrm(size)?;
+                ("imul", [d.register(reg, size), rm])
+            }
+            opcode @ (0xb6 | 0xb7 | 0xbe | 0xbf) => {
+                let (reg, rm) = d.modrm(if opcode & 1 == 0 { 1 } else { 2 })?;
+                let mnemonic = if opcode < 0xbe { "movzx" } else { "movsx" };
+                (mnemonic, [d.register(reg, size), rm])
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(Instruction { address, len: d.pos, mnemonic, operands })
+}
+
+// The instruction at `address` for fault reports: never faults itself, and
+// prints `<unmapped>` or the raw bytes when it cannot decode.
+pub struct At(pub VirtAddr);
+
+impl fmt::Display for At {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let start = self.0;
+        let end = start + (MAX_INSTRUCTION_LEN as u64 - 1);
+        write!(f, "{:#x}: ", start.as_u64())?;
+        if !crate::memory::is_mapped(start) || !crate::memory::is_mapped(end) {
+            return f.write_str("<unmapped>");
+        }
+        let mut bytes = [0u8; MAX_INSTRUCTION_LEN];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile(start.as_ptr::<u8>().add(i)) };
+        }
+        match decode(&bytes, start.as_u64()) {
+            Some(instruction) => write!(f, "{}", instruction),
+            None => {
+                f.write_str("(bad)")?;
+                for byte in &bytes[..4] {
+                    write!(f, " {:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_decode_known_instructions() {
+    use crate::test_util::TextBuffer;
+
+    let cases: [(&[u8], &str); 14] = [
+        (&[0x48, 0x89, 0xd8], "mov rax, rbx"),
+        (&[0x48, 0x8b, 0x43, 0x08], "mov rax, [rbx+0x8]"),
+        (&[0x8b, 0x44, 0x8d, 0xf0], "mov eax, [rbp+rcx*4-0x10]"),
+        (&[0xc7, 0x04, 0x24, 0x2a, 0x00, 0x00, 0x00], "mov dword ptr [rsp], 0x2a"),
+        (&[0x48, 0x83, 0xec, 0x10], "sub rsp, 0x10"),
+        (&[0x48, 0x8d, 0x05, 0x10, 0x00, 0x00, 0x00], "lea rax, [0x1017]"),
+        (&[0xe8, 0xfb, 0x0f, 0x00, 0x00], "call 0x2000"),
+        (&[0x74, 0xfe], "je 0x1000"),
+        (&[0xff, 0xe0], "jmp rax"),
+        (&[0x41, 0x54], "push r12"),
+        (&[0x40, 0x88, 0xf0], "mov al, sil"),
+        (&[0x88, 0xf0], "mov al, dh"),
+        (&[0x48, 0xb8, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11], "mov rax, 0x1122334455667788"),
+        (&[0x0f, 0x0b], "ud2"),
+    ];
+    for (bytes, expected) in cases {
+        let instruction = decode(bytes, 0x1000).unwrap();
+        assert_eq!(instruction.len, bytes.len());
+        let mut text = TextBuffer::<64>::new();
+        fmt::write(&mut text, format_args!("{}", instruction)).unwrap();
+        assert_eq!(text.as_str(), expected);
+    }
+
+    assert_eq!(decode(&[0x0f, 0xff], 0x1000), None);
+    assert_eq!(decode(&[0xe8, 0x00], 0x1000), None);
+}
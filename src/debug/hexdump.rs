@@ -0,0 +1,91 @@
+use core::fmt::{self, Write};
+use crate::error::{KernelError, KernelResult};
+use crate::{cmdline, memory};
+use x86_64::VirtAddr;
+
+const BYTES_PER_LINE: usize = 16;
+// Keeps a typo from flooding the serial console.
+const MAX_DUMP: usize = 64 * 1024;
+
+// `xxd`-style lines: address, 16 hex bytes, then the printable ASCII.
+pub fn hexdump<W: Write>(out: &mut W, address: u64, bytes: &[u8]) -> fmt::Result {
+    for (line, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        write!(out, "{:016x}:", address + (line * BYTES_PER_LINE) as u64)?;
+        for column in 0..BYTES_PER_LINE {
+            match chunk.get(column) {
+                Some(byte) => write!(out, " {:02x}", byte)?,
+                None => out.write_str("   ")?,
+            }
+        }
+        out.write_str("  ")?;
+        for &byte in chunk {
+            let printable = byte.is_ascii_graphic() || byte == b' ';
+            out.write_char(if printable { byte as char } else { '.' })?;
+        }
+        out.write_char('\n')?;
+    }
+    Ok(())
+}
+
+// Dumps kernel virtual memory after checking that every page of the range
+// is mapped, so a bad address is an error instead of a page fault.
+pub fn dump_memory<W: Write>(out: &mut W, address: u64, len: usize) -> KernelResult<()> {
+    if len == 0 || len > MAX_DUMP {
+        return Err(KernelError::InvalidArgument);
+    }
+    let last = address.checked_add(len as u64 - 1).ok_or(KernelError::InvalidArgument)?;
+    let start = VirtAddr::try_new(address).map_err(|_| KernelError::InvalidArgument)?;
+    let end = VirtAddr::try_new(last).map_err(|_| KernelError::InvalidArgument)?;
+    let mut page = start.align_down(4096u64);
+    while page <= end {
+        if !memory::is_mapped(page) {
+            return Err(KernelError::MappingFailed);
+        }
+        page += 4096u64;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(start.as_ptr::<u8>(), len) };
+    hexdump(out, address, bytes).map_err(|_| KernelError::IoError)
+}
+
+// The console's `x <addr> <len>` command; both numbers may be decimal or
+// `0x` hex. The dump goes to serial.
+pub fn command(args: &str) -> KernelResult<()> {
+    let mut args = args.split_ascii_whitespace().map(cmdline::parse_u64);
+    let (Some(Some(address)), Some(Some(len)), None) = (args.next(), args.next(), args.next()) else {
+        return Err(KernelError::InvalidArgument);
+    };
+    let len = usize::try_from(len).map_err(|_| KernelError::InvalidArgument)?;
+    dump_memory(&mut crate::serial::SerialWriter, address, len)
+}
+
+#[test_case]
+fn test_dump_vga_buffer_and_reject_unmapped() {
+    use crate::test_util::TextBuffer;
+    use crate::vga_buffer::{Color, ColorCode, WRITER};
+    use x86_64::instructions::interrupts;
+
+    let mut out = TextBuffer::<256>::new();
+    // Hold the writer so nothing scrolls the cells away before the dump.
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        for (col, &byte) in b"MentOS hexdump!!".iter().enumerate() {
+            writer.write_at(0, col, byte, ColorCode::new(Color::White, Color::Blue)).unwrap();
+        }
+        dump_memory(&mut out, 0xb8000, 32).unwrap();
+    });
+    assert_eq!(out.as_str(), concat!(
+        "00000000000b8000: 4d 1f 65 1f 6e 1f 74 1f 4f 1f 53 1f 20 1f 68 1f  M.e.n.t.O.S. .h.\n",
+        "00000000000b8010: 65 1f 78 1f 64 1f 75 1f 6d 1f 70 1f 21 1f 21 1f  e.x.d.u.m.p.!.!.\n",
+    ));
+
+    let mut out = TextBuffer::<256>::new();
+    hexdump(&mut out, 0x1000, b"\x00~\x7f").unwrap();
+    assert_eq!(out.as_str(),
+        "0000000000001000: 00 7e 7f                                         .~.\n");
+
+    assert_eq!(command(" 0x6000_0000_0000 16"), Err(KernelError::InvalidArgument));
+    assert_eq!(command("0x600000000000 16"), Err(KernelError::MappingFailed));
+    assert_eq!(command("0xb8000"), Err(KernelError::InvalidArgument));
+    assert_eq!(command("0xb8000 32"), Ok(()));
+    assert_eq!(command("753664 16"), Ok(()));
+}
@@ -0,0 +1,99 @@
+use core::fmt;
+use spin::Mutex;
+use crate::error::{KernelError, KernelResult};
+use crate::log::{self, Level};
+
+const MAX_DEVICES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceClass {
+    InterruptController,
+    Serial,
+    Input,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceStatus {
+    Ok,
+    Failed(KernelError),
+    Absent,
+}
+
+impl DeviceStatus {
+    // A driver that found no hardware is absent, not broken.
+    pub fn from_result(result: &KernelResult<()>) -> Self {
+        match result {
+            Ok(()) => DeviceStatus::Ok,
+            Err(KernelError::DeviceNotFound) => DeviceStatus::Absent,
+            Err(err) => DeviceStatus::Failed(*err),
+        }
+    }
+}
+
+impl fmt::Display for DeviceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceStatus::Ok => f.write_str("ok"),
+            DeviceStatus::Failed(err) => write!(f, "failed ({})", err),
+            DeviceStatus::Absent => f.write_str("absent"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Device {
+    pub name: &'static str,
+    pub class: DeviceClass,
+    pub status: DeviceStatus,
+}
+
+static DEVICES: Mutex<[Option<Device>; MAX_DEVICES]> = Mutex::new([None; MAX_DEVICES]);
+
+// Drivers register once init has finished, whatever the outcome, so a failed
+// driver still shows up. Registering a name again replaces its entry, e.g.
+// after a recovery.
+pub fn register(device: Device) {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    let registered = without_interrupts(|| {
+        let mut devices = DEVICES.lock();
+        let slot = devices
+            .iter()
+            .position(|slot| slot.is_some_and(|existing| existing.name == device.name))
+            .or_else(|| devices.iter().position(Option::is_none))?;
+        devices[slot] = Some(device);
+        Some(())
+    });
+    match registered {
+        Some(()) => log::kv!(Level::Debug, "device", name = device.name, status = device.status),
+        None => log::kv!(Level::Warn, "device registry full", name = device.name),
+    }
+}
+
+// A snapshot of every registered device, in registration order.
+pub fn list() -> impl Iterator<Item = Device> {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    without_interrupts(|| *DEVICES.lock()).into_iter().flatten()
+}
+
+#[test_case]
+fn test_failed_driver_stays_listed() {
+    let mock_init = |fail: Option<KernelError>| fail.map_or(Ok(()), Err);
+
+    for (name, result) in [
+        ("mock-ok", mock_init(None)),
+        ("mock-broken", mock_init(Some(KernelError::Timeout))),
+        ("mock-missing", mock_init(Some(KernelError::DeviceNotFound))),
+    ] {
+        register(Device { name, class: DeviceClass::Input, status: DeviceStatus::from_result(&result) });
+    }
+    let status = |name| list().find(|device| device.name == name).map(|device| device.status);
+    assert_eq!(status("mock-ok"), Some(DeviceStatus::Ok));
+    assert_eq!(status("mock-broken"), Some(DeviceStatus::Failed(KernelError::Timeout)));
+    assert_eq!(status("mock-missing"), Some(DeviceStatus::Absent));
+
+    register(Device { name: "mock-broken", class: DeviceClass::Input, status: DeviceStatus::Ok });
+    assert_eq!(status("mock-broken"), Some(DeviceStatus::Ok));
+    assert_eq!(list().filter(|device| device.name == "mock-broken").count(), 1);
+}
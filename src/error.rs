@@ -0,0 +1,29 @@
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelError {
+    OutOfMemory,
+    MappingFailed,
+    DeviceNotFound,
+    InvalidArgument,
+    WouldBlock,
+    Timeout,
+    IoError,
+}
+
+impl fmt::Display for KernelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            KernelError::OutOfMemory => "out of memory",
+            KernelError::MappingFailed => "mapping failed",
+            KernelError::DeviceNotFound => "device not found",
+            KernelError::InvalidArgument => "invalid argument",
+            KernelError::WouldBlock => "resource busy",
+            KernelError::Timeout => "timed out",
+            KernelError::IoError => "I/O error",
+        };
+        f.write_str(message)
+    }
+}
+
+pub type KernelResult<T> = Result<T, KernelError>;
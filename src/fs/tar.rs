@@ -0,0 +1,152 @@
+use crate::error::{KernelError, KernelResult};
+
+const BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    Other(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<'a> {
+    pub prefix: &'a str,
+    pub name: &'a str,
+    pub mode: u32,
+    pub size: usize,
+    pub kind: EntryKind,
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Archive<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Archive<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Archive { data }
+    }
+
+    pub fn entries(&self) -> Entries<'a> {
+        Entries { remaining: self.data }
+    }
+
+    pub fn find(&self, name: &str) -> Option<Entry<'a>> {
+        self.entries()
+            .map_while(Result::ok)
+            .find(|entry| entry.name == name && entry.prefix.is_empty())
+    }
+}
+
+pub struct Entries<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = KernelResult<Entry<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.remaining.get(..BLOCK_SIZE)?;
+        // The archive ends with zero blocks.
+        if header.iter().all(|&byte| byte == 0) {
+            self.remaining = &[];
+            return None;
+        }
+
+        let entry = parse_entry(self.remaining);
+        match entry {
+            Ok(entry) => {
+                let padded = entry.size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+                self.remaining = &self.remaining[BLOCK_SIZE + padded..];
+            }
+            Err(_) => self.remaining = &[],
+        }
+        Some(entry)
+    }
+}
+
+fn parse_entry(block: &[u8]) -> KernelResult<Entry<'_>> {
+    let header = &block[..BLOCK_SIZE];
+    if &header[257..262] != b"ustar" {
+        return Err(KernelError::InvalidArgument);
+    }
+    if parse_octal(&header[148..156])? != checksum(header) {
+        return Err(KernelError::InvalidArgument);
+    }
+
+    let size = parse_octal(&header[124..136])? as usize;
+    let data = block
+        .get(BLOCK_SIZE..BLOCK_SIZE + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE)
+        .ok_or(KernelError::InvalidArgument)?;
+    let kind = match header[156] {
+        b'0' | 0 => EntryKind::File,
+        b'5' => EntryKind::Directory,
+        b'2' => EntryKind::Symlink,
+        other => EntryKind::Other(other),
+    };
+
+    Ok(Entry {
+        prefix: parse_str(&header[345..500])?,
+        name: parse_str(&header[0..100])?,
+        mode: parse_octal(&header[100..108])? as u32,
+        size,
+        kind,
+        data: &data[..size],
+    })
+}
+
+// The checksum is the byte sum of the header with its own field read as spaces.
+fn checksum(header: &[u8]) -> u64 {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| if (148..156).contains(&i) { u64::from(b' ') } else { u64::from(byte) })
+        .sum()
+}
+
+// Numeric fields are ASCII octal, optionally space padded and NUL or space terminated.
+fn parse_octal(field: &[u8]) -> KernelResult<u64> {
+    let digits = field
+        .iter()
+        .skip_while(|&&byte| byte == b' ')
+        .take_while(|&&byte| byte != 0 && byte != b' ');
+    let mut value: u64 = 0;
+    for &digit in digits {
+        if !(b'0'..=b'7').contains(&digit) {
+            return Err(KernelError::InvalidArgument);
+        }
+        value = value
+            .checked_mul(8)
+            .ok_or(KernelError::InvalidArgument)?
+            + u64::from(digit - b'0');
+    }
+    Ok(value)
+}
+
+fn parse_str(field: &[u8]) -> KernelResult<&str> {
+    let len = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).map_err(|_| KernelError::InvalidArgument)
+}
+
+#[test_case]
+fn test_parse_two_file_archive() {
+    let archive = Archive::new(include_bytes!("testdata/two_files.tar"));
+    let mut entries = archive.entries();
+
+    let hello = entries.next().unwrap().unwrap();
+    assert_eq!(hello.name, "hello.txt");
+    assert_eq!(hello.mode, 0o644);
+    assert_eq!(hello.kind, EntryKind::File);
+    assert_eq!(hello.data, b"Hello, MentOS!\n");
+
+    let motd = entries.next().unwrap().unwrap();
+    assert_eq!(motd.name, "etc/motd");
+    assert_eq!(motd.size, 600);
+    assert!(motd.data.iter().all(|&byte| byte == b'x'));
+
+    assert!(entries.next().is_none());
+    assert_eq!(archive.find("etc/motd").map(|entry| entry.mode), Some(0o600));
+}
@@ -4,25 +4,36 @@ use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor, SegmentSelector
 use lazy_static::lazy_static;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+const INTERRUPT_STACK_SIZE: usize = 4096 * 5;
+
+// Each use is a separate stack; the TSS takes the address past its end.
+macro_rules! interrupt_stack {
+    () => {{
+        static mut STACK: [u8; INTERRUPT_STACK_SIZE] = [0; INTERRUPT_STACK_SIZE];
+
+        VirtAddr::from_ptr(core::ptr::addr_of!(STACK)) + INTERRUPT_STACK_SIZE
+    }};
+}
 
 lazy_static! {
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = {
-                let ptr = unsafe { core::ptr::addr_of!(STACK) };
-                VirtAddr::from_ptr(ptr)
-            };
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = interrupt_stack!();
+        tss.interrupt_stack_table[NMI_IST_INDEX as usize] = interrupt_stack!();
+        tss.interrupt_stack_table[MACHINE_CHECK_IST_INDEX as usize] = interrupt_stack!();
         tss
     };
 }
 
+// The stack the CPU switches to for interrupt stack table entry `index`.
+pub fn interrupt_stack(index: u16) -> core::ops::Range<VirtAddr> {
+    let end = TSS.interrupt_stack_table[usize::from(index)];
+    end - INTERRUPT_STACK_SIZE..end
+}
+
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
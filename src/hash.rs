@@ -0,0 +1,120 @@
+// Checksums for on-disk and on-wire formats. Neither is cryptographic.
+
+// CRC-32 as used by zlib, PNG, GPT and Ethernet: reflected polynomial
+// 0xEDB88320, initial value and final XOR 0xFFFFFFFF.
+const CRC32_POLYNOMIAL: u32 = 0xedb8_8320;
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ CRC32_POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+// Incremental CRC-32: feeding the data in any split gives the same result.
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Crc32 { state: !0 }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            let index = (self.state ^ u32::from(byte)) & 0xff;
+            self.state = (self.state >> 8) ^ CRC32_TABLE[index as usize];
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+// Largest prime below 2^16.
+const ADLER_MODULUS: u32 = 65_521;
+// Bytes that can be summed before `b` might overflow a u32 (from zlib).
+const ADLER_CHUNK: usize = 5552;
+
+// Incremental Adler-32 as used by zlib streams.
+#[derive(Debug, Clone, Copy)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    pub const fn new() -> Self {
+        Adler32 { a: 1, b: 0 }
+    }
+
+    // The modulo is taken once per chunk rather than once per byte.
+    pub fn update(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(ADLER_CHUNK) {
+            for &byte in chunk {
+                self.a += u32::from(byte);
+                self.b += self.a;
+            }
+            self.a %= ADLER_MODULUS;
+            self.b %= ADLER_MODULUS;
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut adler = Adler32::new();
+    adler.update(data);
+    adler.finish()
+}
+
+#[test_case]
+fn test_standard_vectors() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    assert_eq!(adler32(b""), 1);
+
+    // Split updates, including chunks past the Adler-32 overflow bound.
+    let data = [0xffu8; 3 * ADLER_CHUNK + 7];
+    let (mut crc, mut adler) = (Crc32::new(), Adler32::new());
+    for part in data.chunks(1000) {
+        crc.update(part);
+        adler.update(part);
+    }
+    assert_eq!(crc.finish(), crc32(&data));
+    assert_eq!(adler.finish(), adler32(&data));
+}
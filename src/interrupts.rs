@@ -1,21 +1,31 @@
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::structures::idt::{
+    DivergingHandlerFunc, DivergingHandlerFuncWithErrCode, Entry, EntryOptions, HandlerFunc,
+    HandlerFuncWithErrCode, InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+    PageFaultHandlerFunc,
+};
 use lazy_static::lazy_static;
 use crate::println;
 use crate::gdt;
+use crate::debug::disasm::At;
 use pic8259::ChainedPics;
+use crate::sync::{level, OrderedMutex};
 use spin;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use crate::error::{KernelError, KernelResult};
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = 40;
 
-pub static PICS: spin::Mutex<ChainedPics> =
-    spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+pub static PICS: OrderedMutex<ChainedPics> =
+    OrderedMutex::new("pics", level::PICS, unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Com1 = PIC_1_OFFSET + 4,
+    Mouse = PIC_1_OFFSET + 12,
 }
 
 impl InterruptIndex {
@@ -28,25 +38,380 @@ impl InterruptIndex {
     }
 }
 
-lazy_static! {
-    static ref IDT: InterruptDescriptorTable = {
+// An exception vector tagged with the handler signature the CPU expects for
+// it, so installing a handler with the wrong signature does not compile.
+pub struct Exception<F> {
+    entry: fn(&mut InterruptDescriptorTable) -> &mut Entry<F>,
+}
+
+macro_rules! exceptions {
+    ($($handler:ty { $($name:ident => $field:ident,)* })*) => {
+        $(impl Exception<$handler> {
+            $(pub const $name: Self = {
+                fn entry(idt: &mut InterruptDescriptorTable) -> &mut Entry<$handler> {
+                    &mut idt.$field
+                }
+                Exception { entry }
+            };)*
+        })*
+    };
+}
+
+exceptions! {
+    HandlerFunc {
+        DIVIDE_ERROR => divide_error,
+        DEBUG => debug,
+        NON_MASKABLE_INTERRUPT => non_maskable_interrupt,
+        BREAKPOINT => breakpoint,
+        OVERFLOW => overflow,
+        BOUND_RANGE_EXCEEDED => bound_range_exceeded,
+        INVALID_OPCODE => invalid_opcode,
+        DEVICE_NOT_AVAILABLE => device_not_available,
+        X87_FLOATING_POINT => x87_floating_point,
+        SIMD_FLOATING_POINT => simd_floating_point,
+        VIRTUALIZATION => virtualization,
+    }
+    HandlerFuncWithErrCode {
+        INVALID_TSS => invalid_tss,
+        SEGMENT_NOT_PRESENT => segment_not_present,
+        STACK_SEGMENT_FAULT => stack_segment_fault,
+        GENERAL_PROTECTION_FAULT => general_protection_fault,
+        ALIGNMENT_CHECK => alignment_check,
+        SECURITY_EXCEPTION => security_exception,
+    }
+    PageFaultHandlerFunc {
+        PAGE_FAULT => page_fault,
+    }
+    DivergingHandlerFunc {
+        MACHINE_CHECK => machine_check,
+    }
+    DivergingHandlerFuncWithErrCode {
+        DOUBLE_FAULT => double_fault,
+    }
+}
+
+pub trait Handler: Copy + 'static {
+    fn install(self, entry: &mut Entry<Self>) -> &mut EntryOptions;
+}
+
+macro_rules! impl_handler {
+    ($($handler:ty),*) => {
+        $(impl Handler for $handler {
+            fn install(self, entry: &mut Entry<Self>) -> &mut EntryOptions {
+                entry.set_handler_fn(self)
+            }
+        })*
+    };
+}
+
+impl_handler!(HandlerFunc, HandlerFuncWithErrCode, PageFaultHandlerFunc,
+    DivergingHandlerFunc, DivergingHandlerFuncWithErrCode);
+
+pub struct IdtBuilder {
+    idt: InterruptDescriptorTable,
+}
+
+impl IdtBuilder {
+    // Every vector starts out on the default handler.
+    pub fn new() -> Self {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        x86_64::set_general_handler!(&mut idt, unhandled_interrupt_handler);
+        IdtBuilder { idt }
+    }
+
+    pub fn set_exception<F: Handler>(&mut self, exception: Exception<F>, handler: F)
+        -> &mut EntryOptions
+    {
+        handler.install((exception.entry)(&mut self.idt))
+    }
+
+    pub fn set_irq(&mut self, irq: InterruptIndex, handler: HandlerFunc) -> &mut EntryOptions {
+        self.idt[irq.as_usize()].set_handler_fn(handler)
+    }
+
+    pub fn build(self) -> InterruptDescriptorTable {
+        self.idt
+    }
+}
+
+impl Default for IdtBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Vectors with a handler installed at boot; everything else above 31 is free
+// for `register`.
+const BOOT_IRQS: [InterruptIndex; 4] = [
+    InterruptIndex::Timer,
+    InterruptIndex::Keyboard,
+    InterruptIndex::Com1,
+    InterruptIndex::Mouse,
+];
+
+struct LiveIdt {
+    table: InterruptDescriptorTable,
+    registered: [u64; 4],
+}
+
+impl LiveIdt {
+    fn is_registered(&self, vector: u8) -> bool {
+        self.registered[usize::from(vector / 64)] & (1 << (vector % 64)) != 0
+    }
+
+    fn set_registered(&mut self, vector: u8, registered: bool) {
+        let word = &mut self.registered[usize::from(vector / 64)];
+        if registered {
+            *word |= 1 << (vector % 64);
+        } else {
+            *word &= !(1 << (vector % 64));
+        }
+    }
+}
+
+lazy_static! {
+    // The CPU reads the table in place, so it stays at this address for
+    // good; the lock only serializes updates.
+    static ref IDT: spin::Mutex<LiveIdt> = spin::Mutex::new(LiveIdt {
+        table: boot_idt(),
+        registered: [0; 4],
+    });
+
+    static ref DEFAULT_IDT: InterruptDescriptorTable = IdtBuilder::new().build();
+}
+
+fn boot_idt() -> InterruptDescriptorTable {
+    let mut idt = IdtBuilder::new();
+    idt.set_exception(Exception::DIVIDE_ERROR, divide_error_handler);
+    idt.set_exception(Exception::BREAKPOINT, breakpoint_handler);
+    idt.set_exception(Exception::OVERFLOW, overflow_handler);
+    idt.set_exception(Exception::BOUND_RANGE_EXCEEDED, bound_range_handler);
+    unsafe {
+        idt.set_exception(Exception::NON_MASKABLE_INTERRUPT, nmi_handler)
+            .set_stack_index(gdt::NMI_IST_INDEX);
+        idt.set_exception(Exception::DOUBLE_FAULT, double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        idt.set_exception(Exception::MACHINE_CHECK, machine_check_handler)
+            .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+    }
+    idt.set_exception(Exception::PAGE_FAULT, page_fault_handler);
+    idt.set_exception(Exception::GENERAL_PROTECTION_FAULT, general_protection_fault_handler);
+    idt.set_exception(Exception::INVALID_OPCODE, invalid_opcode_handler);
+    idt.set_exception(Exception::ALIGNMENT_CHECK, alignment_check_handler);
+    idt.set_irq(InterruptIndex::Timer, timer_interrupt_handler);
+    idt.set_irq(InterruptIndex::Keyboard, keyboard_interrupt_handler);
+    idt.set_irq(InterruptIndex::Com1, com1_interrupt_handler);
+    idt.set_irq(InterruptIndex::Mouse, mouse_interrupt_handler);
+    idt.build()
+}
+
+pub fn init_idt() {
+    let idt = IDT.lock();
+    unsafe { idt.table.load_unsafe() };
+}
+
+// Installs a handler for a free vector at runtime and unmasks its PIC line.
+// Entries are rewritten with interrupts disabled, and the CPU only reads
+// them on delivery, so no interrupt sees a half-written entry. The table
+// never moves, so no reload is needed.
+pub fn register(vector: u8, handler: HandlerFunc) -> KernelResult<()> {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    if vector < 32 || BOOT_IRQS.iter().any(|irq| irq.as_u8() == vector) {
+        return Err(KernelError::InvalidArgument);
+    }
+    without_interrupts(|| {
+        let mut idt = IDT.lock();
+        if idt.is_registered(vector) {
+            return Err(KernelError::InvalidArgument);
+        }
+        idt.table[usize::from(vector)].set_handler_fn(handler);
+        idt.set_registered(vector, true);
+        Ok(())
+    })?;
+    set_pic_mask(vector, false);
+    Ok(())
+}
+
+// Masks the vector's PIC line again and puts the default handler back.
+pub fn unregister(vector: u8) -> KernelResult<()> {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    without_interrupts(|| {
+        let mut idt = IDT.lock();
+        if vector < 32 || !idt.is_registered(vector) {
+            return Err(KernelError::InvalidArgument);
+        }
+        set_pic_mask(vector, true);
+        idt.table[usize::from(vector)] = DEFAULT_IDT[usize::from(vector)];
+        idt.set_registered(vector, false);
+        Ok(())
+    })
+}
+
+// Bumped every time an interrupt arrives on the vector; see `wait_for_irq`.
+static IRQ_SEQUENCE: [AtomicU32; 256] = [const { AtomicU32::new(0) }; 256];
+
+// Called by interrupt handlers to wake anything waiting on their vector.
+// The boot handlers and the default handler already do this; handlers
+// installed with `register` have to call it themselves.
+pub fn signal_irq(vector: u8) {
+    IRQ_SEQUENCE[usize::from(vector)].fetch_add(1, Ordering::Release);
+}
+
+pub fn irq_sequence(vector: u8) -> u32 {
+    IRQ_SEQUENCE[usize::from(vector)].load(Ordering::Acquire)
+}
+
+// Sleeps until an interrupt arrives on `vector` after `seen` was read with
+// `irq_sequence`. Reading the sequence before checking the device means an
+// interrupt that fires between the check and the sleep is not missed:
+//
+//     let seen = interrupts::irq_sequence(vector);
+//     if !device_ready() {
+//         interrupts::wait_for_irq_since(vector, seen);
+//     }
+pub fn wait_for_irq_since(vector: u8, seen: u32) -> u32 {
+    use x86_64::instructions::interrupts;
+
+    loop {
+        interrupts::disable();
+        let sequence = irq_sequence(vector);
+        if sequence != seen {
+            interrupts::enable();
+            return sequence;
+        }
+        crate::cpu::idle();
+    }
+}
+
+// Sleeps until the next interrupt on `vector`.
+pub fn wait_for_irq(vector: u8) -> u32 {
+    wait_for_irq_since(vector, irq_sequence(vector))
+}
+
+static UNHANDLED_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+
+pub fn unhandled_interrupts() -> u64 {
+    UNHANDLED_INTERRUPTS.load(Ordering::Relaxed)
+}
+
+pub fn unmask(index: InterruptIndex) {
+    set_pic_mask(index.as_u8(), false);
+}
+
+// Does nothing for vectors outside the PIC range. Unmasking a slave line
+// also unmasks the cascade; masking one leaves the cascade alone.
+fn set_pic_mask(vector: u8, masked: bool) {
+    if !(PIC_1_OFFSET..PIC_2_OFFSET + 8).contains(&vector) {
+        return;
+    }
+    let irq = vector - PIC_1_OFFSET;
+    let (master_bits, slave_bits) = if irq < 8 {
+        (1 << irq, 0)
+    } else {
+        (1 << 2, 1 << (irq - 8))
+    };
+    // An IRQ arriving now would spin on the PICS lock for its EOI.
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
         unsafe {
-            idt.double_fault.set_handler_fn(double_fault_handler)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+            let [mut master, mut slave] = pics.read_masks();
+            if masked {
+                master |= if irq < 8 { master_bits } else { 0 };
+                slave |= slave_bits;
+            } else {
+                master &= !master_bits;
+                slave &= !slave_bits;
+            }
+            pics.write_masks(master, slave);
+        }
+    });
+}
+
+fn unhandled_interrupt_handler(
+    stack_frame: InterruptStackFrame, index: u8, error_code: Option<u64>)
+{
+    if index < 32 {
+        panic!("EXCEPTION: UNHANDLED VECTOR {} (error code {:?})\n{:#?}",
+            index, error_code, stack_frame);
+    }
+
+    UNHANDLED_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+    crate::serial_println!("Unhandled interrupt on vector {}", index);
+
+    if (PIC_1_OFFSET..PIC_2_OFFSET + 8).contains(&index) {
+        let irq = index - PIC_1_OFFSET;
+        let mut pics = PICS.lock();
+        if is_spurious_irq(irq) {
+            // A spurious IRQ 15 was still forwarded by the master PIC.
+            if irq == 15 {
+                unsafe { pics.notify_end_of_interrupt(PIC_1_OFFSET + 2) };
+            }
+            return;
         }
-        idt.page_fault.set_handler_fn(page_fault_handler);
-        idt[InterruptIndex::Timer.as_usize()]
-            .set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_usize()]
-            .set_handler_fn(keyboard_interrupt_handler);
-        idt
+        unsafe { pics.notify_end_of_interrupt(index) };
+    }
+    signal_irq(index);
+}
+
+// IRQ 7 and IRQ 15 are raised spuriously when a request disappears before
+// it is acknowledged; the in-service register then has no bit set for it.
+fn is_spurious_irq(irq: u8) -> bool {
+    use x86_64::instructions::port::Port;
+
+    const READ_ISR: u8 = 0x0b;
+
+    let command_port = match irq {
+        7 => 0x20,
+        15 => 0xa0,
+        _ => return false,
     };
+    let mut port: Port<u8> = Port::new(command_port);
+    unsafe {
+        port.write(READ_ISR);
+        port.read() & (1 << 7) == 0
+    }
 }
 
-pub fn init_idt() {
-    IDT.load();
+extern "x86-interrupt" fn divide_error_handler(
+    stack_frame: InterruptStackFrame)
+{
+    panic!("EXCEPTION: DIVIDE ERROR at {:?}\n{:#?}",
+        stack_frame.instruction_pointer, stack_frame);
+}
+
+static NMIS: AtomicU64 = AtomicU64::new(0);
+
+pub fn nmi_count() -> u64 {
+    NMIS.load(Ordering::Relaxed)
+}
+
+extern "x86-interrupt" fn nmi_handler(
+    stack_frame: InterruptStackFrame)
+{
+    use x86_64::instructions::port::Port;
+
+    // System control port B latches the two legacy NMI sources.
+    const PARITY_ERROR: u8 = 1 << 7;
+    const IO_CHANNEL_CHECK: u8 = 1 << 6;
+
+    let status: u8 = unsafe { Port::new(0x61).read() };
+    let cause = if status & PARITY_ERROR != 0 {
+        "memory parity error"
+    } else if status & IO_CHANNEL_CHECK != 0 {
+        "I/O channel check"
+    } else {
+        "unknown source"
+    };
+
+    NMIS.fetch_add(1, Ordering::Relaxed);
+    // An NMI can arrive while a serial lock is held; eprintln! takes none.
+    crate::eprintln!("NMI: {} (port 0x61 = {:#04x})", cause, status);
+
+    if status & (PARITY_ERROR | IO_CHANNEL_CHECK) != 0 {
+        panic!("EXCEPTION: NON-MASKABLE INTERRUPT ({})\n{:#?}", cause, stack_frame);
+    }
 }
 
 extern "x86-interrupt" fn breakpoint_handler(
@@ -55,6 +420,25 @@ extern "x86-interrupt" fn breakpoint_handler(
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
+// INTO and BOUND are invalid opcodes in 64-bit mode (they raise #UD), so
+// #OF and #BR can only come from `int 4`/`int 5`. Both are logged and
+// resumed; with no userspace there is no process to signal.
+static OVERFLOW_OR_BOUND_EXCEPTIONS: AtomicU64 = AtomicU64::new(0);
+
+extern "x86-interrupt" fn overflow_handler(
+    stack_frame: InterruptStackFrame)
+{
+    OVERFLOW_OR_BOUND_EXCEPTIONS.fetch_add(1, Ordering::Relaxed);
+    crate::serial_println!("EXCEPTION: OVERFLOW\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn bound_range_handler(
+    stack_frame: InterruptStackFrame)
+{
+    OVERFLOW_OR_BOUND_EXCEPTIONS.fetch_add(1, Ordering::Relaxed);
+    crate::serial_println!("EXCEPTION: BOUND RANGE EXCEEDED\n{:#?}", stack_frame);
+}
+
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame, _error_code: u64) -> !
 {
@@ -70,13 +454,79 @@ extern "x86-interrupt" fn page_fault_handler(
     println!("EXCEPTION: PAGE FAULT");
     println!("Accessed Address: {:?}", Cr2::read());
     println!("Error Code: {:?}", error_code);
+    println!("Instruction: {}", At(stack_frame.instruction_pointer));
     println!("{:#?}", stack_frame);
     hlt_loop();
 }
 
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame, error_code: u64)
+{
+    panic!("EXCEPTION: GENERAL PROTECTION FAULT\nError Code: {:#x}\nInstruction: {}\n{:#?}",
+        error_code, At(stack_frame.instruction_pointer), stack_frame);
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(
+    stack_frame: InterruptStackFrame)
+{
+    panic!("EXCEPTION: INVALID OPCODE\nInstruction: {}\n{:#?}",
+        At(stack_frame.instruction_pointer), stack_frame);
+}
+
+// #AC is only raised at CPL 3, and there is no userspace to signal yet, so
+// any alignment check fault is a kernel bug.
+extern "x86-interrupt" fn alignment_check_handler(
+    stack_frame: InterruptStackFrame, error_code: u64)
+{
+    panic!("EXCEPTION: ALIGNMENT CHECK\nError Code: {:#x}\n{:#?}",
+        error_code, stack_frame);
+}
+
+extern "x86-interrupt" fn machine_check_handler(
+    stack_frame: InterruptStackFrame) -> !
+{
+    use x86_64::registers::model_specific::Msr;
+
+    const IA32_MCG_CAP: u32 = 0x179;
+    const IA32_MCG_STATUS: u32 = 0x17a;
+    const IA32_MC0_STATUS: u32 = 0x401;
+    const STATUS_VALID: u64 = 1 << 63;
+    const STATUS_MISC_VALID: u64 = 1 << 59;
+    const STATUS_ADDR_VALID: u64 = 1 << 58;
+
+    // The machine may be in a bad state: report with the lock-free serial
+    // path first, before anything that could take a lock.
+    let (capabilities, status) = unsafe {
+        (Msr::new(IA32_MCG_CAP).read(), Msr::new(IA32_MCG_STATUS).read())
+    };
+    crate::eprintln!("MACHINE CHECK: IA32_MCG_STATUS = {:#x}", status);
+    for bank in 0..(capabilities & 0xff) as u32 {
+        let status_msr = IA32_MC0_STATUS + 4 * bank;
+        let bank_status = unsafe { Msr::new(status_msr).read() };
+        if bank_status & STATUS_VALID == 0 {
+            continue;
+        }
+        crate::eprintln!("  bank {}: status {:#x}", bank, bank_status);
+        if bank_status & STATUS_ADDR_VALID != 0 {
+            let addr = unsafe { Msr::new(status_msr + 1).read() };
+            crate::eprintln!("  bank {}: address {:#x}", bank, addr);
+        }
+        if bank_status & STATUS_MISC_VALID != 0 {
+            let misc = unsafe { Msr::new(status_msr + 2).read() };
+            crate::eprintln!("  bank {}: misc {:#x}", bank, misc);
+        }
+    }
+
+    panic!("EXCEPTION: MACHINE CHECK\n{:#?}", stack_frame);
+}
+
 extern "x86-interrupt" fn timer_interrupt_handler(
-    _stack_frame: InterruptStackFrame)
+    stack_frame: InterruptStackFrame)
 {
+    crate::profile::record(stack_frame.instruction_pointer.as_u64(), stack_frame.code_segment);
+    crate::cpu::timer_tick();
+    crate::clocksource::timer_tick();
+    signal_irq(InterruptIndex::Timer.as_u8());
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
@@ -86,20 +536,141 @@ extern "x86-interrupt" fn timer_interrupt_handler(
 extern "x86-interrupt" fn keyboard_interrupt_handler(
     _stack_frame: InterruptStackFrame)
 {
-    use x86_64::instructions::port::Port;
-
-    let mut port = Port::new(0x60);
-    let scancode: u8 = unsafe { port.read() };
-    crate::keyboard::add_scancode(scancode);
+    if let Some(scancode) = crate::ps2::try_read_data() {
+        crate::keyboard::add_scancode(scancode);
+    }
 
+    signal_irq(InterruptIndex::Keyboard.as_u8());
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
     }
 }
 
+extern "x86-interrupt" fn com1_interrupt_handler(
+    _stack_frame: InterruptStackFrame)
+{
+    crate::serial::handle_interrupt();
+
+    signal_irq(InterruptIndex::Com1.as_u8());
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Com1.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(
+    _stack_frame: InterruptStackFrame)
+{
+    if let Some(byte) = crate::ps2::try_read_data() {
+        crate::mouse::add_byte(byte);
+    }
+
+    signal_irq(InterruptIndex::Mouse.as_u8());
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Mouse.as_u8());
+    }
+}
+
 pub fn hlt_loop() -> ! {
     loop {
         x86_64::instructions::hlt();
     }
 }
+
+#[test_case]
+fn test_unhandled_software_interrupt() {
+    let before = unhandled_interrupts();
+    unsafe { core::arch::asm!("int 0x80") };
+    assert_eq!(unhandled_interrupts(), before + 1);
+}
+
+#[test_case]
+fn test_builder_installs_breakpoint_handler() {
+    use core::sync::atomic::AtomicBool;
+
+    static HIT: AtomicBool = AtomicBool::new(false);
+
+    extern "x86-interrupt" fn test_breakpoint_handler(
+        _stack_frame: InterruptStackFrame)
+    {
+        HIT.store(true, Ordering::Relaxed);
+    }
+
+    lazy_static! {
+        static ref TEST_IDT: InterruptDescriptorTable = {
+            let mut idt = IdtBuilder::new();
+            idt.set_exception(Exception::BREAKPOINT, test_breakpoint_handler);
+            idt.build()
+        };
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        TEST_IDT.load();
+        x86_64::instructions::interrupts::int3();
+        init_idt();
+    });
+    assert!(HIT.load(Ordering::Relaxed));
+}
+
+#[test_case]
+fn test_register_and_unregister_at_runtime() {
+    static HITS: AtomicU64 = AtomicU64::new(0);
+
+    extern "x86-interrupt" fn test_handler(
+        _stack_frame: InterruptStackFrame)
+    {
+        HITS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    assert_eq!(register(3, test_handler), Err(KernelError::InvalidArgument));
+    assert_eq!(register(InterruptIndex::Timer.as_u8(), test_handler),
+        Err(KernelError::InvalidArgument));
+
+    register(0x81, test_handler).unwrap();
+    assert_eq!(register(0x81, test_handler), Err(KernelError::InvalidArgument));
+    unsafe { core::arch::asm!("int 0x81") };
+    assert_eq!(HITS.load(Ordering::Relaxed), 1);
+
+    unregister(0x81).unwrap();
+    let unhandled = unhandled_interrupts();
+    unsafe { core::arch::asm!("int 0x81") };
+    assert_eq!(HITS.load(Ordering::Relaxed), 1);
+    assert_eq!(unhandled_interrupts(), unhandled + 1);
+    assert_eq!(unregister(0x81), Err(KernelError::InvalidArgument));
+}
+
+#[test_case]
+fn test_wait_for_irq() {
+    // The sequence taken before the interrupt makes the wait return at once.
+    let seen = irq_sequence(0x82);
+    unsafe { core::arch::asm!("int 0x82") };
+    assert_eq!(wait_for_irq_since(0x82, seen), seen + 1);
+
+    let ticks = irq_sequence(InterruptIndex::Timer.as_u8());
+    assert!(wait_for_irq(InterruptIndex::Timer.as_u8()) > ticks);
+}
+
+#[test_case]
+fn test_overflow_and_bound_range_resume() {
+    let before = OVERFLOW_OR_BOUND_EXCEPTIONS.load(Ordering::Relaxed);
+    unsafe { core::arch::asm!("int 4", "int 5") };
+    assert_eq!(OVERFLOW_OR_BOUND_EXCEPTIONS.load(Ordering::Relaxed), before + 2);
+}
+
+#[test_case]
+fn test_nmi_runs_on_its_own_stack_and_returns() {
+    let stack = gdt::interrupt_stack(gdt::NMI_IST_INDEX);
+    let before = nmi_count();
+    let interrupted_rsp: u64;
+    // A software NMI has no legacy source behind it, so the boot handler
+    // only logs it and returns.
+    unsafe { core::arch::asm!("mov {}, rsp", "int 2", out(reg) interrupted_rsp) };
+    assert_eq!(nmi_count(), before + 1);
+
+    // The CPU aligns the IST pointer down to 16 bytes, then pushes SS and
+    // the interrupted RSP, so the frame sits at the top of the NMI stack.
+    let saved_rsp = (stack.end.align_down(16u64) - 16u64).as_ptr::<u64>();
+    assert_eq!(unsafe { saved_rsp.read_volatile() }, interrupted_rsp);
+}
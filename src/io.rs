@@ -0,0 +1,57 @@
+use crate::keyboard::{self, Key};
+use crate::print;
+
+const BACKSPACE: char = '\u{8}';
+
+pub fn getchar() -> char {
+    loop {
+        if let Key::Char(character) = keyboard::next_key() {
+            return character;
+        }
+    }
+}
+
+pub fn read_line(buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    loop {
+        match getchar() {
+            '\n' => {
+                print!("\n");
+                return len;
+            }
+            BACKSPACE => {
+                if len > 0 {
+                    len -= 1;
+                    print!("{}", BACKSPACE);
+                }
+            }
+            character if character.is_ascii() && !character.is_ascii_control()
+                && len < buf.len() =>
+            {
+                buf[len] = character as u8;
+                len += 1;
+                print!("{}", character);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test_case]
+fn test_read_line_from_injected_scancodes() {
+    use x86_64::instructions::interrupts;
+
+    // Scancode set 1 make and break codes for "hx", backspace, "i", enter.
+    let scancodes = [
+        0x23, 0xa3, 0x2d, 0xad, 0x0e, 0x8e, 0x17, 0x97, 0x1c, 0x9c,
+    ];
+    interrupts::without_interrupts(|| {
+        for scancode in scancodes {
+            keyboard::add_scancode(scancode);
+        }
+    });
+
+    let mut buf = [0u8; 16];
+    let len = read_line(&mut buf);
+    assert_eq!(&buf[..len], b"hi");
+}
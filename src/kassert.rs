@@ -0,0 +1,72 @@
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::{eprintln, exit_qemu, exit_token, hlt_loop, serial_println, QemuExitCode};
+
+static EXPECT_FAILURE: AtomicBool = AtomicBool::new(false);
+
+// For tests that are supposed to fail an assertion: the next failure exits
+// QEMU with success instead.
+pub fn expect_failure() {
+    EXPECT_FAILURE.store(true, Ordering::Relaxed);
+}
+
+#[doc(hidden)]
+pub fn fail(message: fmt::Arguments, file: &str, line: u32) -> ! {
+    if EXPECT_FAILURE.load(Ordering::Relaxed) {
+        serial_println!("[ok]");
+        exit_qemu(exit_token(), QemuExitCode::Success);
+        hlt_loop();
+    }
+    eprintln!("[failed]\n{}\n  at {}:{}", message, file, line);
+    exit_qemu(exit_token(), QemuExitCode::Failed);
+    hlt_loop();
+}
+
+#[macro_export]
+macro_rules! kassert {
+    ($cond:expr $(,)?) => {
+        if !$cond {
+            $crate::kassert::fail(
+                format_args!("kassert!({})", stringify!($cond)), file!(), line!());
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! kassert_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if !(*left == *right) {
+                    $crate::kassert::fail(
+                        format_args!("kassert_eq!({}, {})\n  left: {:?}\n right: {:?}",
+                            stringify!($left), stringify!($right), left, right),
+                        file!(), line!());
+                }
+            }
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! kassert_ne {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if *left == *right {
+                    $crate::kassert::fail(
+                        format_args!("kassert_ne!({}, {})\n  left: {:?}\n right: {:?}",
+                            stringify!($left), stringify!($right), left, right),
+                        file!(), line!());
+                }
+            }
+        }
+    };
+}
+
+#[test_case]
+fn test_passing_assertions_are_silent() {
+    kassert!(1 < 2);
+    kassert_eq!(2 + 2, 4);
+    kassert_ne!(2 + 2, 5);
+}
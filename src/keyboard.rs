@@ -1,24 +1,354 @@
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-use spin::Mutex;
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyState, Keyboard, ScancodeSet1};
 use lazy_static::lazy_static;
-use crate::print;
+use crate::collections::RingBuffer;
+use crate::error::{KernelError, KernelResult};
+use crate::ps2;
+use crate::sync::{level, OrderedMutex};
+
+const KEY_QUEUE_SIZE: usize = 64;
+const SET_TYPEMATIC: u8 = 0xF3;
+const SCANCODE_SET: u8 = 0xF0;
+const GET_SCANCODE_SET: u8 = 0x00;
 
 lazy_static! {
-    static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-        Mutex::new(Keyboard::new(ScancodeSet1::new(),
+    static ref KEYBOARD: OrderedMutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
+        OrderedMutex::new("keyboard", level::KEYBOARD, Keyboard::new(ScancodeSet1::new(),
             layouts::Us104Key, HandleControl::Ignore)
         );
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Special(SpecialKey),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialKey {
+    ArrowUp,
+    ArrowDown,
+    ArrowLeft,
+    ArrowRight,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+}
+
+impl SpecialKey {
+    fn from_keycode(code: KeyCode) -> Option<Self> {
+        let key = match code {
+            KeyCode::ArrowUp => SpecialKey::ArrowUp,
+            KeyCode::ArrowDown => SpecialKey::ArrowDown,
+            KeyCode::ArrowLeft => SpecialKey::ArrowLeft,
+            KeyCode::ArrowRight => SpecialKey::ArrowRight,
+            KeyCode::Home => SpecialKey::Home,
+            KeyCode::End => SpecialKey::End,
+            KeyCode::PageUp => SpecialKey::PageUp,
+            KeyCode::PageDown => SpecialKey::PageDown,
+            KeyCode::Insert => SpecialKey::Insert,
+            KeyCode::Delete => SpecialKey::Delete,
+            KeyCode::F1 => SpecialKey::F1,
+            KeyCode::F2 => SpecialKey::F2,
+            KeyCode::F3 => SpecialKey::F3,
+            KeyCode::F4 => SpecialKey::F4,
+            KeyCode::F5 => SpecialKey::F5,
+            KeyCode::F6 => SpecialKey::F6,
+            KeyCode::F7 => SpecialKey::F7,
+            KeyCode::F8 => SpecialKey::F8,
+            KeyCode::F9 => SpecialKey::F9,
+            KeyCode::F10 => SpecialKey::F10,
+            KeyCode::F11 => SpecialKey::F11,
+            KeyCode::F12 => SpecialKey::F12,
+            _ => return None,
+        };
+        Some(key)
+    }
+}
+
+// Keys that arrive while the queue is full are dropped.
+static KEY_QUEUE: OrderedMutex<RingBuffer<Key, KEY_QUEUE_SIZE>> =
+    OrderedMutex::new("key queue", level::KEY_QUEUE, RingBuffer::new());
+
+// Held keys, indexed by `KeyCode as u8`. Storing the code itself rather
+// than a bit lets `pressed_keys` hand codes back without a reverse table.
+static PRESSED: OrderedMutex<[Option<KeyCode>; 256]> =
+    OrderedMutex::new("pressed keys", level::PRESSED_KEYS, [None; 256]);
+
 pub fn add_scancode(scancode: u8) {
     let mut keyboard = KEYBOARD.lock();
 
     if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => print!("{}", character),
-                DecodedKey::RawKey(key) => print!("{:?}", key),
+        let code = key_event.code;
+        match key_event.state {
+            KeyState::Down => PRESSED.lock()[code as usize] = Some(code),
+            KeyState::Up => PRESSED.lock()[code as usize] = None,
+            KeyState::SingleShot => {}
+        }
+        // The US layout turns Delete (0xE0 0x53) into U+007F, so catch it
+        // before it is decoded as a character.
+        let delete = key_event.code == KeyCode::Delete && key_event.state == KeyState::Down;
+        let key = match keyboard.process_keyevent(key_event) {
+            _ if delete => Some(Key::Special(SpecialKey::Delete)),
+            Some(DecodedKey::Unicode(character)) => Some(Key::Char(character)),
+            Some(DecodedKey::RawKey(code)) => SpecialKey::from_keycode(code).map(Key::Special),
+            None => None,
+        };
+        if let Some(key) = key {
+            let _ = KEY_QUEUE.lock().push(key);
+        }
+    }
+}
+
+pub fn is_pressed(code: KeyCode) -> bool {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| PRESSED.lock()[code as usize].is_some())
+}
+
+// A snapshot of the keys held right now, in `KeyCode` order.
+pub fn pressed_keys() -> impl Iterator<Item = KeyCode> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| *PRESSED.lock()).into_iter().flatten()
+}
+
+// Forgets every held key. Break codes can be lost while the keyboard is
+// reconfigured, and a key would otherwise read as held forever.
+pub fn release_all() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| *PRESSED.lock() = [None; 256]);
+}
+
+// Recovers from a stuck controller (output buffer full, no more IRQ 1) and
+// drops decoder and held-key state that no longer matches the keyboard.
+pub fn reset_controller() -> KernelResult<()> {
+    use x86_64::instructions::interrupts;
+
+    ps2::reset_controller()?;
+    interrupts::without_interrupts(|| {
+        *KEYBOARD.lock() = Keyboard::new(ScancodeSet1::new(), layouts::Us104Key, HandleControl::Ignore);
+    });
+    release_all();
+    Ok(())
+}
+
+pub fn pop_key() -> Option<Key> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| KEY_QUEUE.lock().pop())
+}
+
+pub fn next_key() -> Key {
+    use x86_64::instructions::interrupts;
+
+    loop {
+        // Check and sleep with interrupts off so a key arriving in between
+        // still wakes the `hlt` instead of being missed.
+        interrupts::disable();
+        // Popped first: a guard in the match scrutinee would stay held while
+        // idling, and the IRQ 1 handler would spin on it forever.
+        let key = KEY_QUEUE.lock().pop();
+        match key {
+            Some(key) => {
+                interrupts::enable();
+                return key;
             }
+            None => crate::cpu::idle(),
         }
     }
 }
+
+// The keyboard repeats a held key by resending its make code until the break
+// code arrives, so repeats decode like ordinary presses.
+pub fn set_repeat_rate(delay_ms: u32, rate_cps: u32) -> KernelResult<()> {
+    ps2::send_keyboard_byte(SET_TYPEMATIC)?;
+    ps2::send_keyboard_byte(typematic_byte(delay_ms, rate_cps))
+}
+
+// The decoder only understands set 1, so that is what has to reach the CPU:
+// set 1 is sent untranslated, set 2 goes through the controller's
+// translation. Set 3 cannot be translated and is rejected.
+pub fn set_scancode_set(set: u8) -> KernelResult<()> {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    let translate = match set {
+        1 => false,
+        2 => true,
+        _ => return Err(KernelError::InvalidArgument),
+    };
+    without_interrupts(|| {
+        // Switch with translation off so the command bytes pass unchanged.
+        let config = ps2::read_config()?;
+        ps2::write_config(config & !ps2::CONFIG_TRANSLATION)?;
+        ps2::send_keyboard_byte(SCANCODE_SET)?;
+        ps2::send_keyboard_byte(set)?;
+        let config = if translate {
+            config | ps2::CONFIG_TRANSLATION
+        } else {
+            config & !ps2::CONFIG_TRANSLATION
+        };
+        ps2::write_config(config)
+    })?;
+    release_all();
+    Ok(())
+}
+
+// The set the keyboard itself is using.
+pub fn scancode_set() -> KernelResult<u8> {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    without_interrupts(|| {
+        let translated = ps2::read_config()? & ps2::CONFIG_TRANSLATION != 0;
+        ps2::send_keyboard_byte(SCANCODE_SET)?;
+        ps2::send_keyboard_byte(GET_SCANCODE_SET)?;
+        let reply = ps2::read_data()?;
+        untranslate_set_reply(reply, translated).ok_or(KernelError::DeviceNotFound)
+    })
+}
+
+// With translation on, the reply to "get scancode set" is itself translated
+// like a scancode.
+fn untranslate_set_reply(reply: u8, translated: bool) -> Option<u8> {
+    match (reply, translated) {
+        (1..=3, false) => Some(reply),
+        (0x43, true) => Some(1),
+        (0x41, true) => Some(2),
+        (0x3f, true) => Some(3),
+        _ => None,
+    }
+}
+
+// Bits 5-6 select a 250-1000 ms delay, bits 0-4 a repeat period of
+// (8 + bits 0-2) * 2^(bits 3-4) * 4.17 ms, i.e. 30 down to 2 characters/s.
+fn typematic_byte(delay_ms: u32, rate_cps: u32) -> u8 {
+    let delay = (delay_ms.clamp(250, 1000) + 125) / 250 - 1;
+    let rate_centi_cps = rate_cps * 100;
+    let rate = (0..32u32)
+        .min_by_key(|&rate| {
+            let period_us = (8 + (rate & 7)) * (1 << (rate >> 3)) * 4170;
+            (100_000_000 / period_us).abs_diff(rate_centi_cps)
+        })
+        .unwrap_or(0);
+    ((delay << 5) | rate) as u8
+}
+
+#[test_case]
+fn test_typematic_byte_encoding() {
+    assert_eq!(typematic_byte(250, 30), 0x00);
+    assert_eq!(typematic_byte(1000, 2), 0x7F);
+    assert_eq!(typematic_byte(500, 10), 0x2C);
+}
+
+#[test_case]
+fn test_held_key_repeats_until_released() {
+    use x86_64::instructions::{hlt, interrupts};
+
+    const A_MAKE: u8 = 0x1e;
+    const A_BREAK: u8 = 0x9e;
+
+    // Repeated make codes, one per timer tick, followed by the break code.
+    for _ in 0..3 {
+        interrupts::without_interrupts(|| add_scancode(A_MAKE));
+        hlt();
+    }
+    interrupts::without_interrupts(|| add_scancode(A_BREAK));
+
+    for _ in 0..3 {
+        assert_eq!(pop_key(), Some(Key::Char('a')));
+    }
+    assert_eq!(pop_key(), None);
+}
+
+#[test_case]
+fn test_extended_delete_is_a_special_key() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        for scancode in [0xe0, 0x53, 0xe0, 0xd3, 0xe0, 0x48, 0xe0, 0xc8] {
+            add_scancode(scancode);
+        }
+    });
+    assert_eq!(pop_key(), Some(Key::Special(SpecialKey::Delete)));
+    assert_eq!(pop_key(), Some(Key::Special(SpecialKey::ArrowUp)));
+    assert_eq!(pop_key(), None);
+}
+
+#[test_case]
+fn test_pressed_keys_follow_make_and_break() {
+    use x86_64::instructions::interrupts;
+
+    const A_MAKE: u8 = 0x1e;
+    const S_MAKE: u8 = 0x1f;
+    const A_BREAK: u8 = 0x9e;
+
+    interrupts::without_interrupts(|| {
+        add_scancode(A_MAKE);
+        add_scancode(S_MAKE);
+    });
+    assert!(is_pressed(KeyCode::A) && is_pressed(KeyCode::S));
+    assert!(pressed_keys().eq([KeyCode::A, KeyCode::S]));
+
+    interrupts::without_interrupts(|| add_scancode(A_BREAK));
+    assert!(!is_pressed(KeyCode::A));
+    assert!(pressed_keys().eq([KeyCode::S]));
+
+    release_all();
+    assert_eq!(pressed_keys().count(), 0);
+    while pop_key().is_some() {}
+}
+
+#[test_case]
+fn test_reset_controller_clears_full_buffer() {
+    use x86_64::instructions::interrupts;
+
+    // Controller command: place the next data byte in the output buffer as
+    // if the keyboard had sent it.
+    const WRITE_KEYBOARD_OUTPUT: u8 = 0xD2;
+
+    interrupts::without_interrupts(|| {
+        ps2::write_command(WRITE_KEYBOARD_OUTPUT).unwrap();
+        ps2::write_data(0x1e).unwrap();
+        reset_controller().unwrap();
+        assert_eq!(ps2::flush_output(), 0);
+        let config = ps2::read_config().unwrap();
+        assert_ne!(config & ps2::CONFIG_KEYBOARD_INTERRUPT, 0);
+        assert_eq!(config & ps2::CONFIG_KEYBOARD_CLOCK_DISABLED, 0);
+    });
+    // IRQ 1 is still latched from the byte, but finds the buffer empty.
+    assert_eq!(pop_key(), None);
+    assert_eq!(pressed_keys().count(), 0);
+    assert_eq!(scancode_set(), Ok(2));
+}
+
+#[test_case]
+fn test_scancode_set_selection() {
+    assert_eq!(untranslate_set_reply(0x41, true), Some(2));
+    assert_eq!(untranslate_set_reply(2, false), Some(2));
+    assert_eq!(untranslate_set_reply(0x41, false), None);
+    assert_eq!(set_scancode_set(3), Err(KernelError::InvalidArgument));
+
+    set_scancode_set(1).unwrap();
+    assert_eq!(scancode_set(), Ok(1));
+    // Back to what the BIOS left: set 2, translated.
+    set_scancode_set(2).unwrap();
+    assert_eq!(scancode_set(), Ok(2));
+    // The ACKs and replies were read by the commands, not decoded as keys.
+    assert_eq!(pop_key(), None);
+    assert_eq!(pressed_keys().count(), 0);
+}
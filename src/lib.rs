@@ -2,23 +2,60 @@
 #![cfg_attr(test, no_main)]
 #![feature(abi_x86_interrupt)]
 #![feature(custom_test_frameworks)]
-#![cfg_attr(test, test_framework(crate::test_framework))]
+#![cfg_attr(test, test_runner(crate::test_framework))]
 #![cfg_attr(test, reexport_test_harness_main = "test_main")]
 
 use core::panic::PanicInfo;
 
 pub mod vga_buffer;
+pub mod terminal;
 pub mod serial;
 pub mod gdt;
 pub mod interrupts;
 pub mod keyboard;
+pub mod ps2;
+pub mod mouse;
+pub mod io;
 pub mod memory;
+pub mod cmdline;
+pub mod boot;
+pub mod error;
+pub mod bench;
+pub mod cpu;
+pub mod device;
+pub mod profile;
+pub mod clocksource;
+pub mod cmos;
+pub mod platform;
+pub mod smbios;
+pub mod rand;
+pub mod kassert;
+pub mod panic;
+pub mod log;
+pub mod sync;
+pub mod collections;
+pub mod hash;
+pub mod fs;
+pub mod serialize;
+pub mod debug;
+pub mod selftest;
+#[cfg(test)]
+mod test_util;
 
 pub fn init() {
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
+    interrupts::unmask(interrupts::InterruptIndex::Com1);
+    register_boot_device("pic8259", device::DeviceClass::InterruptController);
+    register_boot_device("com1", device::DeviceClass::Serial);
     x86_64::instructions::interrupts::enable();
+    clocksource::init();
+}
+
+// Set up unconditionally during `init`, so they are always reported OK.
+fn register_boot_device(name: &'static str, class: device::DeviceClass) {
+    device::register(device::Device { name, class, status: device::DeviceStatus::Ok });
 }
 
 pub fn hlt_loop() -> ! {
@@ -27,6 +64,25 @@ pub fn hlt_loop() -> ! {
     }
 }
 
+// The reason is kept in CMOS and reported by the next boot.
+pub fn reboot(reason: cmos::RebootReason) -> ! {
+    use x86_64::instructions::tables::lidt;
+    use x86_64::structures::DescriptorTablePointer;
+
+    x86_64::instructions::interrupts::disable();
+    cmos::set_reboot_reason(reason);
+    // Pulse the CPU reset line through the keyboard controller.
+    let _ = ps2::write_command(0xFE);
+
+    // Fall back to a triple fault: with an empty IDT any exception resets.
+    let empty = DescriptorTablePointer { limit: 0, base: x86_64::VirtAddr::zero() };
+    unsafe {
+        lidt(&empty);
+        core::arch::asm!("int3");
+    }
+    hlt_loop();
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum QemuExitCode {
@@ -34,9 +90,32 @@ pub enum QemuExitCode {
     Failed = 0x11,
 }
 
-pub fn exit_qemu(exit_code: QemuExitCode) {
+// Proof that an exit was requested on purpose: `exit_qemu` ignores any
+// token but the one `exit_token` hands out. It only guards against stray or
+// accidental exits, e.g. a write to port 0xf4 with a made-up value; any code
+// in the kernel can call `exit_token`, so it is not a security boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitToken(u64);
+
+static EXIT_TOKEN: spin::Once<u64> = spin::Once::new();
+
+pub fn exit_token() -> ExitToken {
+    ExitToken(*EXIT_TOKEN.call_once(rand::random_u64))
+}
+
+// Does nothing outside QEMU, where port 0xf4 may belong to real hardware.
+pub fn exit_qemu(token: ExitToken, exit_code: QemuExitCode) {
     use x86_64::instructions::port::Port;
 
+    if token != exit_token() {
+        serial_println!("exit_qemu: ignoring {:?} with bad token {:#x}", exit_code, token.0);
+        return;
+    }
+    serial_println!("exit_qemu: {:?} (token {:#x})", exit_code, token.0);
+    serial::flush();
+    if !platform::is_qemu() {
+        return;
+    }
     unsafe {
         let mut port = Port::new(0xf4);
         port.write(exit_code as u32);
@@ -63,14 +142,13 @@ pub fn test_framework(tests: &[&dyn Testable]) {
     for test in tests {
         test.run();
     }
-    exit_qemu(QemuExitCode::Success);
+    exit_qemu(exit_token(), QemuExitCode::Success);
 }
 
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[failed]\n");
-    serial_println!("Error: {}\n", info);
-    exit_qemu(QemuExitCode::Failed);
-    hlt_loop();
+    eprintln!("Error: {}\n", info);
+    panic::finish();
 }
 
 #[cfg(test)]
@@ -80,8 +158,13 @@ use bootloader::{entry_point, BootInfo};
 entry_point!(test_kernel_main);
 
 #[cfg(test)]
-fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
+static BOOT_INFO: spin::Once<&'static BootInfo> = spin::Once::new();
+
+#[cfg(test)]
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    BOOT_INFO.call_once(|| boot_info);
     init();
+    unsafe { memory::init(x86_64::VirtAddr::new(boot_info.physical_memory_offset)) };
     test_main();
     hlt_loop();
 }
@@ -96,3 +179,10 @@ fn panic(info: &PanicInfo) -> ! {
 fn test_breakpoint_exception() {
     x86_64::instructions::interrupts::int3();
 }
+
+#[test_case]
+fn test_exit_with_wrong_token_is_ignored() {
+    let wrong = ExitToken(exit_token().0 ^ 1);
+    exit_qemu(wrong, QemuExitCode::Failed);
+    // Still running: the exit above did not reach QEMU.
+}
@@ -0,0 +1,113 @@
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    const ALL: [Level; 5] = [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace];
+
+    // Names accepted by the `loglevel=` command line option.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn max_level() -> Level {
+    Level::ALL[usize::from(MAX_LEVEL.load(Ordering::Relaxed))]
+}
+
+pub fn enabled(level: Level) -> bool {
+    level <= max_level()
+}
+
+// One structured log line: `LEVEL message key1=value1 key2=value2`.
+pub struct Record<'a> {
+    pub level: Level,
+    pub message: &'a str,
+    pub fields: &'a [(&'a str, &'a dyn fmt::Display)],
+}
+
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.level, self.message)?;
+        for (key, value) in self.fields {
+            write!(f, " {}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log_kv {
+    ($level:expr, $message:expr $(, $key:ident = $value:expr)* $(,)?) => {{
+        let level = $level;
+        if $crate::log::enabled(level) {
+            $crate::serial_println!("{}", $crate::log::Record {
+                level,
+                message: $message,
+                fields: &[$((stringify!($key), &$value as &dyn core::fmt::Display)),*],
+            });
+        }
+    }};
+}
+
+// `log::kv!(Level::Info, "frame allocator ready", free = n)` logs over
+// serial if `level` passes the `loglevel=` filter.
+pub use __log_kv as kv;
+
+#[test_case]
+fn test_kv_record_format() {
+    use crate::test_util::TextBuffer;
+
+    let mut line = TextBuffer::<64>::new();
+    let record = Record {
+        level: Level::Warn,
+        message: "low memory",
+        fields: &[("free", &42), ("zone", &"dma")],
+    };
+    fmt::write(&mut line, format_args!("{}", record)).unwrap();
+    assert_eq!(line.as_str(), "WARN low memory free=42 zone=dma");
+
+    kv!(Level::Info, "kv test", answer = 42);
+    set_max_level(Level::Error);
+    assert!(!enabled(Level::Warn));
+    set_max_level(Level::Info);
+    assert!(enabled(Level::Info) && !enabled(Level::Debug));
+}
@@ -3,31 +3,133 @@
 
 use core::panic::PanicInfo;
 use bootloader::{BootInfo, entry_point};
-use ment_os::{println, memory};
+use ment_os::{println, serial_println, boot, log, memory, cmdline, io, keyboard, mouse, selftest, vga_buffer};
+use ment_os::device::DeviceClass;
+use ment_os::panic::PanicBehavior;
 
 entry_point!(kernel_main);
 
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
     use x86_64::VirtAddr;
-    use memory::BootInfoFrameAllocator;
+    use memory::BitmapFrameAllocator;
 
+    // Picked first so the banner already goes to the chosen console.
+    if let Some(name) = cmdline::get("console") {
+        match vga_buffer::Console::from_name(name) {
+            Some(console) => vga_buffer::set_console(console),
+            None => println!("[failed] unknown console: {}", name),
+        }
+    }
     println!("MentOS v0.1.0");
     println!("Initializing kernel...");
+    if !cmdline::cmdline().raw().is_empty() {
+        println!("Command line: {}", cmdline::cmdline().raw());
+    }
+    if let Some(reason) = ment_os::cmos::take_reboot_reason() {
+        println!("last reboot reason: {}", reason);
+    }
+    if let Some(name) = cmdline::get("panic") {
+        match PanicBehavior::from_name(name) {
+            Some(behavior) => ment_os::panic::set_behavior(behavior),
+            None => println!("[failed] unknown panic behavior: {}", name),
+        }
+    }
+    if let Some(value) = cmdline::get("randseed") {
+        match cmdline::parse_u64(value) {
+            Some(seed) => ment_os::rand::set_seed(seed),
+            None => println!("[failed] invalid random seed: {}", value),
+        }
+    }
+    if let Some(name) = cmdline::get("loglevel") {
+        match log::Level::from_name(name) {
+            Some(level) => log::set_max_level(level),
+            None => println!("[failed] unknown log level: {}", name),
+        }
+    }
 
+    const BOOT_STAGES: usize = 3;
+    boot::progress(0, BOOT_STAGES, "interrupts");
     ment_os::init();
+    boot::progress(1, BOOT_STAGES, "drivers");
+    // Safe mode brings up only what the console needs, in case an optional
+    // driver is what breaks the boot.
+    let safe_mode = cmdline::flag("safemode");
+    let drivers = [
+        boot::Driver {
+            name: "i8042",
+            class: DeviceClass::Input,
+            init: keyboard::reset_controller,
+            optional: false,
+        },
+        boot::Driver {
+            name: "keyboard",
+            class: DeviceClass::Input,
+            init: || keyboard::set_repeat_rate(500, 10),
+            optional: false,
+        },
+        boot::Driver { name: "mouse", class: DeviceClass::Input, init: init_mouse, optional: true },
+    ];
+    boot::init_drivers(&drivers, safe_mode);
 
+    boot::progress(2, BOOT_STAGES, "memory");
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let _mapper = unsafe { memory::init(phys_mem_offset) };
-    let _frame_allocator = unsafe {
-        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    memory::print_memory_map(&boot_info.memory_map);
+    memory::validate_memory_map(&boot_info.memory_map);
+    log::kv!(log::Level::Info, "firmware", kind = boot::firmware());
+    if let Some(info) = ment_os::smbios::system_info() {
+        log::kv!(log::Level::Info, "smbios",
+            manufacturer = info.manufacturer.unwrap_or("unknown"),
+            product = info.product.unwrap_or("unknown"),
+            bios = info.bios_version.unwrap_or("unknown"),
+            memory_mib = info.memory_mib);
+    }
+    let frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map)
     };
+    let mut frame_allocator = match frame_allocator {
+        Ok(frame_allocator) => {
+            log::kv!(log::Level::Info, "frame allocator ready",
+                free_frames = frame_allocator.free_frames());
+            Some(frame_allocator)
+        }
+        Err(err) => {
+            println!("[failed] frame allocator: {}", err);
+            None
+        }
+    };
+    if let Some(frame_allocator) = frame_allocator.as_mut() {
+        if let Err(err) = memory::init_physical_window(&mut mapper, frame_allocator) {
+            println!("[failed] physical window: {}", err);
+        }
+    }
+    boot::progress(BOOT_STAGES, BOOT_STAGES, "done");
+    if cmdline::flag("dump-json") {
+        dump_json(boot_info, frame_allocator.as_ref().map_or(0, |a| a.free_frames()));
+    }
+    if cmdline::flag("selftest") {
+        match frame_allocator.as_mut() {
+            Some(frame_allocator) => {
+                selftest::run(&mut selftest::Resources { mapper: &mut mapper, frame_allocator });
+            }
+            None => serial_println!("selftest: skipped, no frame allocator"),
+        }
+        ment_os::hlt_loop();
+    }
 
+    if let Some(celsius) = ment_os::cpu::temperature() {
+        println!("CPU temperature: {} C", celsius);
+    }
     println!("Kernel initialized successfully!");
     println!();
     println!("===========================================");
     println!("  Welcome to MentOS - A Minimal OS Kernel");
     println!("===========================================");
     println!();
+    if safe_mode {
+        println!("*** SAFE MODE: optional drivers are disabled ***");
+        println!();
+    }
     println!("Features:");
     println!("  [x] VGA Text Mode Driver");
     println!("  [x] Serial Port Output");
@@ -38,13 +140,69 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     println!("  [x] GDT & TSS");
     println!();
     println!("Type anything to test keyboard input...");
+    println!("Type `x <addr> <len>` to hex-dump memory over serial.");
     println!();
 
-    ment_os::hlt_loop();
+    let mut line = [0u8; 128];
+    loop {
+        let len = io::read_line(&mut line);
+        let input = core::str::from_utf8(&line[..len]).unwrap_or("");
+        if let Some(args) = input.strip_prefix("x ") {
+            if let Err(err) = ment_os::debug::hexdump::command(args) {
+                println!("x: {}", err);
+            }
+        }
+    }
+}
+
+fn init_mouse() -> ment_os::error::KernelResult<()> {
+    mouse::init()?;
+    mouse::set_callback(|event| vga_buffer::move_pointer(event.dx, event.dy));
+    Ok(())
+}
+
+// Kernel state as a single JSON line over serial, for host-side tools.
+fn dump_json(boot_info: &BootInfo, free_frames: usize) {
+    use ment_os::serial::SerialWriter;
+    use ment_os::serialize::json::JsonWriter;
+
+    let mut json = JsonWriter::new(SerialWriter);
+    let _ = (|| {
+        json.begin_object()?;
+        json.key("memory_map")?;
+        memory::write_memory_map_json(&mut json, &boot_info.memory_map)?;
+        json.key("free_frames")?;
+        json.uint(free_frames as u64)?;
+        json.key("cpu_usage")?;
+        json.uint(ment_os::cpu::usage().into())?;
+        json.key("cpu_temperature")?;
+        match ment_os::cpu::temperature() {
+            Some(celsius) => json.uint(celsius.into())?,
+            None => json.null()?,
+        }
+        json.key("unhandled_interrupts")?;
+        json.uint(ment_os::interrupts::unhandled_interrupts())?;
+        json.key("devices")?;
+        json.begin_array()?;
+        for device in ment_os::device::list() {
+            json.begin_object()?;
+            json.key("name")?;
+            json.string(device.name)?;
+            json.key("class")?;
+            json.display(format_args!("{:?}", device.class))?;
+            json.key("status")?;
+            json.display(device.status)?;
+            json.end_object()?;
+        }
+        json.end_array()?;
+        json.end_object()
+    })();
+    ment_os::serial_println!();
 }
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
-    ment_os::hlt_loop();
+    ment_os::eprintln!("{}", info);
+    let _ = ment_os::try_println!("{}", info);
+    ment_os::panic::finish();
 }
@@ -1,11 +1,29 @@
 use x86_64::{
-    structures::paging::{PageTable, OffsetPageTable, PhysFrame, Size4KiB, FrameAllocator},
+    structures::paging::{
+        PageTable, OffsetPageTable, PhysFrame, Size4KiB, Size2MiB, FrameAllocator,
+        FrameDeallocator, Mapper, Page, PageSize, PageTableFlags, page::PageRange,
+    },
     VirtAddr,
     PhysAddr,
 };
-use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use bootloader::bootinfo::{MemoryMap, MemoryRegion, MemoryRegionType};
+use crate::error::{KernelError, KernelResult};
+use crate::serial_println;
+use crate::serialize::json::JsonWriter;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
 
+const MIN_USABLE_MEMORY: u64 = 4 * 1024 * 1024;
+
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// # Safety
+///
+/// All of physical memory must be mapped at `physical_memory_offset`. Every
+/// call hands out another `&'static mut` to the active level 4 table, so two
+/// returned mappers must never be used at the same time.
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
@@ -24,34 +42,718 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr)
     &mut *page_table_ptr
 }
 
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryMap,
-    next: usize,
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed))
+}
+
+// Whether `addr` is mapped in the active page table; false before `init`.
+// Only walks the tables, so it is safe to call from fault handlers.
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    use x86_64::structures::paging::mapper::Translate;
+
+    let offset = physical_memory_offset();
+    if offset.as_u64() == 0 {
+        return false;
+    }
+    let mapper = unsafe { OffsetPageTable::new(active_level_4_table(offset), offset) };
+    mapper.translate_addr(addr).is_some()
+}
+
+// Clears the frame through the physical memory mapping, so it does not
+// need to be mapped anywhere else.
+/// # Safety
+///
+/// `init` must have run, and nothing may be using the frame: its contents
+/// are overwritten.
+pub unsafe fn zero_frame(frame: PhysFrame) {
+    let virt = physical_memory_offset() + frame.start_address().as_u64();
+    core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, frame.size() as usize);
+}
+
+// Virtual window for `with_physical`, one scratch page per CPU. Only the
+// boot CPU runs for now, so there is a single slot.
+const PHYSICAL_WINDOW: u64 = 0x5f00_0000_0000;
+const PHYSICAL_WINDOW_SLOTS: usize = 1;
+
+// Address of each slot's level 1 entry, reached through the physical memory
+// mapping; zero until `init_physical_window`.
+static WINDOW_ENTRIES: [AtomicU64; PHYSICAL_WINDOW_SLOTS] =
+    [const { AtomicU64::new(0) }; PHYSICAL_WINDOW_SLOTS];
+
+fn window_slot(slot: usize) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_WINDOW) + slot as u64 * Size4KiB::SIZE
 }
 
-impl BootInfoFrameAllocator {
-    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
-            next: 0,
+// Builds the page tables behind the window once, so `with_physical` only
+// rewrites a level 1 entry and never allocates.
+pub fn init_physical_window(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BitmapFrameAllocator,
+) -> KernelResult<()> {
+    use x86_64::structures::paging::PageTableIndex;
+
+    for (slot, entry) in WINDOW_ENTRIES.iter().enumerate() {
+        let page: Page = Page::containing_address(window_slot(slot));
+        // Mapping and unmapping a placeholder leaves the tables in place.
+        let placeholder = PhysFrame::containing_address(PhysAddr::new(0));
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        unsafe { mapper.map_to(page, placeholder, flags, frame_allocator) }
+            .map_err(|_| KernelError::MappingFailed)?
+            .flush();
+        mapper.unmap(page).map_err(|_| KernelError::MappingFailed)?.1.flush();
+
+        let indices: [PageTableIndex; 3] = [page.p4_index(), page.p3_index(), page.p2_index()];
+        let mut table = mapper.level_4_table();
+        for index in indices {
+            let next = physical_memory_offset() + table[index].addr().as_u64();
+            table = unsafe { &mut *next.as_mut_ptr::<PageTable>() };
         }
+        let pte: *mut _ = &mut table[page.p1_index()];
+        entry.store(pte as u64, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+// Runs `f` with a pointer to `phys`, mapped through this CPU's window page
+// rather than the physical memory mapping. Only the one page is flushed on
+// the way in and out. `f` runs with interrupts disabled so nothing else on
+// this CPU can reuse the slot, and must not access beyond the page.
+pub fn with_physical<T>(phys: PhysAddr, f: impl FnOnce(*mut u8) -> T) -> T {
+    use x86_64::instructions::{interrupts, tlb};
+    use x86_64::structures::paging::page_table::PageTableEntry;
+
+    let slot = 0;
+    let entry = WINDOW_ENTRIES[slot].load(Ordering::Relaxed) as *mut PageTableEntry;
+    assert!(!entry.is_null(), "with_physical before init_physical_window");
+    let window = window_slot(slot);
+    interrupts::without_interrupts(|| {
+        let entry = unsafe { &mut *entry };
+        assert!(entry.is_unused(), "with_physical called from inside with_physical");
+        let frame: PhysFrame = PhysFrame::containing_address(phys);
+        entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        tlb::flush(window);
+        let result = f((window + (phys - frame.start_address())).as_mut_ptr());
+        entry.set_unused();
+        tlb::flush(window);
+        result
+    })
+}
+
+pub fn print_memory_map(regions: &[MemoryRegion]) {
+    serial_println!("Memory map:");
+    for region in regions {
+        serial_println!("  {:#012x}-{:#012x} {:?}",
+            region.range.start_addr(), region.range.end_addr(), region.region_type);
     }
+    serial_println!("  {} KiB usable", usable_bytes(regions) / 1024);
+}
+
+// Writes the memory map as `{"regions":[{"start":..,"end":..,"type":".."}],"usable":..}`.
+pub fn write_memory_map_json<W: fmt::Write>(json: &mut JsonWriter<W>, regions: &[MemoryRegion])
+    -> fmt::Result
+{
+    json.begin_object()?;
+    json.key("regions")?;
+    json.begin_array()?;
+    for region in regions {
+        json.begin_object()?;
+        json.key("start")?;
+        json.uint(region.range.start_addr())?;
+        json.key("end")?;
+        json.uint(region.range.end_addr())?;
+        json.key("type")?;
+        json.display(format_args!("{:?}", region.region_type))?;
+        json.end_object()?;
+    }
+    json.end_array()?;
+    json.key("usable")?;
+    json.uint(usable_bytes(regions))?;
+    json.end_object()
+}
 
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        let regions = self.memory_map.iter();
-        let usable_regions = regions
-            .filter(|r| r.region_type == MemoryRegionType::Usable);
-        let addr_ranges = usable_regions
-            .map(|r| r.range.start_addr()..r.range.end_addr());
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryMapIssue {
+    Overlap(MemoryRegion, MemoryRegion),
+    LowUsableMemory(u64),
+}
+
+impl fmt::Display for MemoryMapIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryMapIssue::Overlap(a, b) => write!(f,
+                "{:#x}-{:#x} ({:?}) overlaps {:#x}-{:#x} ({:?})",
+                a.range.start_addr(), a.range.end_addr(), a.region_type,
+                b.range.start_addr(), b.range.end_addr(), b.region_type),
+            MemoryMapIssue::LowUsableMemory(usable) => write!(f,
+                "only {} KiB usable, expected at least {} KiB",
+                usable / 1024, MIN_USABLE_MEMORY / 1024),
+        }
     }
 }
 
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+pub fn memory_map_issues(regions: &[MemoryRegion]) -> impl Iterator<Item = MemoryMapIssue> + '_ {
+    let overlaps = regions.iter().enumerate().flat_map(move |(i, a)| {
+        regions[i + 1..]
+            .iter()
+            .filter(move |b| regions_overlap(a, b))
+            .map(move |b| MemoryMapIssue::Overlap(*a, *b))
+    });
+    let usable = usable_bytes(regions);
+    let low = (usable < MIN_USABLE_MEMORY).then_some(MemoryMapIssue::LowUsableMemory(usable));
+    overlaps.chain(low)
+}
+
+// Logs every issue over serial and returns how many were found.
+pub fn validate_memory_map(regions: &[MemoryRegion]) -> usize {
+    memory_map_issues(regions)
+        .inspect(|issue| serial_println!("memory map warning: {}", issue))
+        .count()
+}
+
+fn regions_overlap(a: &MemoryRegion, b: &MemoryRegion) -> bool {
+    let (a, b) = (&a.range, &b.range);
+    !a.is_empty() && !b.is_empty()
+        && a.start_frame_number < b.end_frame_number
+        && b.start_frame_number < a.end_frame_number
+}
+
+fn usable_bytes(regions: &[MemoryRegion]) -> u64 {
+    regions
+        .iter()
+        .filter(|r| r.region_type == MemoryRegionType::Usable)
+        .map(|r| r.range.end_addr() - r.range.start_addr())
+        .sum()
+}
+
+// Free frames below this count trigger the pressure callbacks.
+pub const PRESSURE_THRESHOLD_FRAMES: usize = 256;
+const MAX_PRESSURE_CALLBACKS: usize = 4;
+
+// Called with the allocator when memory runs low; a callback gives frames
+// back with `deallocate_frame`. It must not allocate.
+pub type PressureCallback = fn(&mut BitmapFrameAllocator);
+
+static PRESSURE_CALLBACKS: spin::Mutex<[Option<PressureCallback>; MAX_PRESSURE_CALLBACKS]> =
+    spin::Mutex::new([None; MAX_PRESSURE_CALLBACKS]);
+
+pub fn register_pressure_callback(callback: PressureCallback) -> KernelResult<()> {
+    let mut callbacks = PRESSURE_CALLBACKS.lock();
+    let slot = callbacks.iter_mut().find(|slot| slot.is_none()).ok_or(KernelError::OutOfMemory)?;
+    *slot = Some(callback);
+    Ok(())
+}
+
+pub fn unregister_pressure_callback(callback: PressureCallback) -> KernelResult<()> {
+    let mut callbacks = PRESSURE_CALLBACKS.lock();
+    let slot = callbacks
+        .iter_mut()
+        .find(|slot| slot.is_some_and(|registered| core::ptr::fn_addr_eq(registered, callback)))
+        .ok_or(KernelError::InvalidArgument)?;
+    *slot = None;
+    Ok(())
+}
+
+const FRAMES_PER_WORD: usize = 64;
+const WORDS_PER_HUGE_FRAME: usize = (Size2MiB::SIZE / Size4KiB::SIZE) as usize / FRAMES_PER_WORD;
+
+// One bit per 4 KiB frame up to the end of usable memory, set when the frame
+// is in use. The bitmap lives in the first usable region large enough to
+// hold it and is reached through the physical memory mapping, so `init`
+// must run first.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    next_free: usize,
+    free: usize,
+    // Set while the pressure callbacks run, so their frees do not re-enter.
+    relieving: bool,
+}
+
+impl BitmapFrameAllocator {
+    /// # Safety
+    ///
+    /// `memory_map` must be accurate: every region it marks usable must
+    /// really be free RAM not in use elsewhere, since the allocator hands it
+    /// out and stores its bitmap there. At most one allocator may be built
+    /// from the same map at a time. `memory::init` must have run.
+    pub unsafe fn init(memory_map: &'static MemoryMap) -> KernelResult<Self> {
+        let usable = || memory_map.iter().filter(|r| r.region_type == MemoryRegionType::Usable);
+        let frames = usable()
+            .map(|r| r.range.end_frame_number as usize)
+            .max()
+            .ok_or(KernelError::OutOfMemory)?;
+        let words = frames.div_ceil(FRAMES_PER_WORD);
+        let bitmap_bytes = (words * 8) as u64;
+
+        let storage = usable()
+            .find(|r| r.range.end_addr() - r.range.start_addr() >= bitmap_bytes)
+            .ok_or(KernelError::OutOfMemory)?
+            .range
+            .start_addr();
+        let virt = physical_memory_offset() + storage;
+        let bitmap = core::slice::from_raw_parts_mut(virt.as_mut_ptr::<u64>(), words);
+        bitmap.fill(!0);
+
+        let mut allocator = BitmapFrameAllocator { bitmap, next_free: 0, free: 0, relieving: false };
+        for region in usable() {
+            for frame in region.range.start_frame_number..region.range.end_frame_number {
+                allocator.set_used(frame as usize, false);
+            }
+        }
+        let storage_frame = (storage / Size4KiB::SIZE) as usize;
+        for frame in storage_frame..storage_frame + bitmap_bytes.div_ceil(Size4KiB::SIZE) as usize {
+            allocator.set_used(frame, true);
+        }
+        if allocator.free == 0 {
+            return Err(KernelError::OutOfMemory);
+        }
+        Ok(allocator)
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.bitmap[frame / FRAMES_PER_WORD] & (1 << (frame % FRAMES_PER_WORD)) != 0
+    }
+
+    fn set_used(&mut self, frame: usize, used: bool) {
+        if self.is_used(frame) == used {
+            return;
+        }
+        let word = &mut self.bitmap[frame / FRAMES_PER_WORD];
+        *word ^= 1 << (frame % FRAMES_PER_WORD);
+        if used {
+            self.free -= 1;
+        } else {
+            self.free += 1;
+            self.next_free = self.next_free.min(frame / FRAMES_PER_WORD);
+        }
+    }
+
+    fn take_frame(&mut self) -> Option<PhysFrame> {
+        // Every word before `next_free` is full, so the scan starts there.
+        let (index, word) = self.bitmap
+            .iter()
+            .enumerate()
+            .skip(self.next_free)
+            .find(|&(_, &word)| word != !0)?;
+        let frame = index * FRAMES_PER_WORD + word.trailing_ones() as usize;
+        self.next_free = index;
+        self.set_used(frame, true);
+        Some(PhysFrame::containing_address(PhysAddr::new(frame as u64 * Size4KiB::SIZE)))
+    }
+
+    // Huge frames need a whole, naturally aligned run of 512 free frames,
+    // which is 8 aligned words of the bitmap.
+    fn take_huge_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let start = (0..self.bitmap.len() / WORDS_PER_HUGE_FRAME)
+            .map(|huge| huge * WORDS_PER_HUGE_FRAME)
+            .find(|&word| self.bitmap[word..word + WORDS_PER_HUGE_FRAME].iter().all(|&w| w == 0))?;
+        for frame in start * FRAMES_PER_WORD..(start + WORDS_PER_HUGE_FRAME) * FRAMES_PER_WORD {
+            self.set_used(frame, true);
+        }
+        let addr = (start * FRAMES_PER_WORD) as u64 * Size4KiB::SIZE;
+        Some(PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+
+    // Runs every registered pressure callback once. The list is copied out
+    // so a callback may register or unregister others.
+    fn relieve_pressure(&mut self) {
+        if self.relieving {
+            return;
+        }
+        let callbacks = *PRESSURE_CALLBACKS.lock();
+        self.relieving = true;
+        for callback in callbacks.into_iter().flatten() {
+            callback(self);
+        }
+        self.relieving = false;
+    }
+
+    // Falls back to the pressure callbacks before giving up, and runs them
+    // when the allocation takes free memory below the threshold.
+    fn allocate_under_pressure<T>(&mut self, allocate: fn(&mut Self) -> Option<T>) -> Option<T> {
+        let was_above = self.free >= PRESSURE_THRESHOLD_FRAMES;
+        let allocated = allocate(self).or_else(|| {
+            self.relieve_pressure();
+            allocate(self)
+        });
+        if was_above && self.free < PRESSURE_THRESHOLD_FRAMES {
+            self.relieve_pressure();
+        }
+        allocated
+    }
+
+    // Kernel frames are handed out as is; frames that end up reachable from
+    // outside the kernel are scrubbed first so they cannot leak old data.
+    pub fn allocate_zeroed_frame(&mut self) -> Option<PhysFrame> {
+        let frame = FrameAllocator::<Size4KiB>::allocate_frame(self)?;
+        unsafe { zero_frame(frame) };
+        Some(frame)
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        self.allocate_under_pressure(Self::take_frame)
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        self.allocate_under_pressure(Self::take_huge_frame)
+    }
+}
+
+impl<S: PageSize> FrameDeallocator<S> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>) {
+        let first = (frame.start_address().as_u64() / Size4KiB::SIZE) as usize;
+        let count = (S::SIZE / Size4KiB::SIZE) as usize;
+        for frame in first..first + count {
+            assert!(self.is_used(frame), "double free of frame {:#x}", frame as u64 * Size4KiB::SIZE);
+            self.set_used(frame, false);
+        }
+    }
+}
+
+// Both addresses are 2 MiB aligned by construction: `Page<Size2MiB>` and
+// `PhysFrame<Size2MiB>` cannot hold anything else.
+pub fn map_huge_page(
+    page: Page<Size2MiB>,
+    flags: PageTableFlags,
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BitmapFrameAllocator,
+) -> KernelResult<PhysFrame<Size2MiB>> {
+    let frame: PhysFrame<Size2MiB> = frame_allocator
+        .allocate_frame()
+        .ok_or(KernelError::OutOfMemory)?;
+    let flush = unsafe { mapper.map_to(page, frame, flags, frame_allocator) }
+        .map_err(|_| KernelError::MappingFailed)?;
+    flush.flush();
+    Ok(frame)
+}
+
+pub fn unmap_huge_page(
+    page: Page<Size2MiB>,
+    mapper: &mut OffsetPageTable,
+) -> KernelResult<PhysFrame<Size2MiB>> {
+    let (frame, flush) = mapper.unmap(page).map_err(|_| KernelError::MappingFailed)?;
+    flush.flush();
+    Ok(frame)
+}
+
+// Past this many pages a single CR3 reload is cheaper than one `invlpg` each
+// plus the misses the reload causes elsewhere.
+const FULL_FLUSH_THRESHOLD: usize = 32;
+
+// Unmaps every mapped 4 KiB page in `pages` and returns its frame to the
+// allocator; pages that are not mapped are skipped. Large ranges flush the
+// whole TLB once at the end instead of page by page. Returns the number of
+// pages unmapped.
+/// # Safety
+///
+/// Every page mapped in `pages` must map a frame that `frame_allocator`
+/// handed out and that nothing else still uses. Unmapping a bootloader,
+/// kernel or MMIO mapping this way would mark reserved frames free, and a
+/// frame past the end of the bitmap panics.
+pub unsafe fn unmap_range(
+    pages: PageRange,
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BitmapFrameAllocator,
+) -> KernelResult<usize> {
+    let batched = pages.count() > FULL_FLUSH_THRESHOLD;
+    unmap_pages(pages, mapper, frame_allocator, batched)
+}
+
+// Same contract as `unmap_range`.
+unsafe fn unmap_pages(
+    pages: PageRange,
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BitmapFrameAllocator,
+    batched: bool,
+) -> KernelResult<usize> {
+    use x86_64::instructions::tlb;
+    use x86_64::structures::paging::mapper::{Translate, TranslateResult, UnmapError};
+
+    let mut unmapped = 0;
+    let mut result = Ok(());
+    for page in pages {
+        // A CR3 reload keeps global entries, so those are always flushed
+        // one by one.
+        let global = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } => flags.contains(PageTableFlags::GLOBAL),
+            _ => false,
+        };
+        match mapper.unmap(page) {
+            Ok((frame, flush)) => {
+                if batched && !global {
+                    flush.ignore();
+                } else {
+                    flush.flush();
+                }
+                // A batched page may stay in the TLB until the flush below.
+                // Freeing the frame now is still sound: the allocator is
+                // borrowed for the whole call, so the frame cannot be handed
+                // out again before the flush. Nothing touches the unmapped
+                // range in between, and this is the only CPU.
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                unmapped += 1;
+            }
+            Err(UnmapError::PageNotMapped) => {}
+            Err(_) => {
+                result = Err(KernelError::MappingFailed);
+                break;
+            }
+        }
+    }
+    // Even on error, the pages unmapped so far must not stay in the TLB.
+    if batched && unmapped > 0 {
+        tlb::flush_all();
+    }
+    result.map(|()| unmapped)
+}
+
+#[test_case]
+fn test_zero_frame_clears_physical_memory() {
+    let mut allocator = test_frame_allocator();
+    let frame: PhysFrame = allocator.allocate_frame().unwrap();
+    let bytes = (physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr::<u8>();
+    unsafe { core::ptr::write_bytes(bytes, 0xA5, Size4KiB::SIZE as usize) };
+
+    // The allocator hands out the lowest free frame, so the freed one comes
+    // straight back.
+    unsafe { allocator.deallocate_frame(frame) };
+    let zeroed = allocator.allocate_zeroed_frame().unwrap();
+    assert_eq!(zeroed, frame);
+    let contents = unsafe { core::slice::from_raw_parts(bytes, Size4KiB::SIZE as usize) };
+    assert!(contents.iter().all(|&byte| byte == 0));
+    unsafe { allocator.deallocate_frame(zeroed) };
+}
+
+#[test_case]
+fn test_validate_flags_overlapping_regions() {
+    use bootloader::bootinfo::FrameRange;
+
+    let region = |start, end, region_type| MemoryRegion {
+        range: FrameRange::new(start, end),
+        region_type,
+    };
+    let mut regions = [
+        region(0x0, 0x9f000, MemoryRegionType::Usable),
+        region(0x100000, 0x8000000, MemoryRegionType::Usable),
+        region(0x400000, 0x500000, MemoryRegionType::Kernel),
+    ];
+
+    let overlap = MemoryMapIssue::Overlap(regions[1], regions[2]);
+    assert!(memory_map_issues(&regions).eq([overlap]));
+
+    regions[1].region_type = MemoryRegionType::Reserved;
+    assert_eq!(validate_memory_map(&regions), 2);
+}
+
+#[test_case]
+fn test_huge_page_mapping_spans_2mib() {
+    let mut mapper = unsafe { init(physical_memory_offset()) };
+    let mut frame_allocator = test_frame_allocator();
+
+    let page: Page<Size2MiB> = Page::containing_address(VirtAddr::new(0x4000_0000_0000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    map_huge_page(page, flags, &mut mapper, &mut frame_allocator).unwrap();
+
+    let first: *mut u64 = page.start_address().as_mut_ptr();
+    let last = unsafe { first.add(Size2MiB::SIZE as usize / 8 - 1) };
+    unsafe {
+        first.write_volatile(0x1111);
+        last.write_volatile(0x2222);
+        assert_eq!(first.read_volatile(), 0x1111);
+        assert_eq!(last.read_volatile(), 0x2222);
+    }
+
+    let frame = unmap_huge_page(page, &mut mapper).unwrap();
+    unsafe { frame_allocator.deallocate_frame(frame) };
+}
+
+#[test_case]
+fn test_with_physical_reaches_frame() {
+    let mut mapper = unsafe { init(physical_memory_offset()) };
+    let mut frame_allocator = test_frame_allocator();
+    init_physical_window(&mut mapper, &mut frame_allocator).unwrap();
+
+    let frame: PhysFrame = frame_allocator.allocate_frame().unwrap();
+    let phys = frame.start_address() + 0x123u64;
+    with_physical(phys, |ptr| unsafe { ptr.write_volatile(0x5a) });
+    let direct = (physical_memory_offset() + phys.as_u64()).as_ptr::<u8>();
+    assert_eq!(unsafe { direct.read_volatile() }, 0x5a);
+    assert_eq!(with_physical(phys, |ptr| unsafe { ptr.read_volatile() }), 0x5a);
+    assert!(!is_mapped(VirtAddr::new(PHYSICAL_WINDOW)));
+    unsafe { frame_allocator.deallocate_frame(frame) };
+}
+
+// Tests share one allocator so they never hand out each other's frames.
+#[cfg(test)]
+fn test_frame_allocator() -> spin::MutexGuard<'static, BitmapFrameAllocator> {
+    lazy_static::lazy_static! {
+        static ref TEST_FRAME_ALLOCATOR: spin::Mutex<BitmapFrameAllocator> = {
+            let boot_info = crate::BOOT_INFO.wait().unwrap();
+            spin::Mutex::new(unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) }.unwrap())
+        };
+    }
+    TEST_FRAME_ALLOCATOR.lock()
+}
+
+#[test_case]
+fn test_bitmap_reuses_freed_frames() {
+    const FRAMES: usize = 3000;
+
+    let mut allocator = test_frame_allocator();
+    let free_before = allocator.free_frames();
+    let mut frames = [0u64; FRAMES];
+    for frame in frames.iter_mut() {
+        let allocated: PhysFrame = allocator.allocate_frame().unwrap();
+        *frame = allocated.start_address().as_u64();
+    }
+    assert_eq!(allocator.free_frames(), free_before - FRAMES);
+
+    // Free every seventh frame, then allocate the same number again.
+    let freed = || frames.iter().step_by(7).copied();
+    for addr in freed() {
+        let frame: PhysFrame = PhysFrame::containing_address(PhysAddr::new(addr));
+        unsafe { allocator.deallocate_frame(frame) };
+    }
+    for addr in freed() {
+        let frame: PhysFrame = allocator.allocate_frame().unwrap();
+        assert_eq!(frame.start_address().as_u64(), addr);
+    }
+
+    for &addr in frames.iter() {
+        let frame: PhysFrame = PhysFrame::containing_address(PhysAddr::new(addr));
+        unsafe { allocator.deallocate_frame(frame) };
+    }
+    assert_eq!(allocator.free_frames(), free_before);
+}
+
+#[test_case]
+fn test_pressure_callback_runs_before_allocation_fails() {
+    // A stand-in for a cache holding a few frames it can give back.
+    static CACHE: spin::Mutex<[Option<u64>; 4]> = spin::Mutex::new([None; 4]);
+    static EVICTED: AtomicU64 = AtomicU64::new(0);
+
+    fn evict(allocator: &mut BitmapFrameAllocator) {
+        for addr in CACHE.lock().iter_mut().filter_map(Option::take) {
+            let frame: PhysFrame = PhysFrame::containing_address(PhysAddr::new(addr));
+            unsafe { allocator.deallocate_frame(frame) };
+            EVICTED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn fill_cache(allocator: &mut BitmapFrameAllocator) {
+        for slot in 0..4 {
+            let frame: PhysFrame = allocator.allocate_frame().unwrap();
+            CACHE.lock()[slot] = Some(frame.start_address().as_u64());
+        }
+    }
+
+    // Without a heap, every allocated frame stores the address of the one
+    // allocated before it, so they can all be freed again.
+    const END: u64 = !0;
+    fn push(allocator: &mut BitmapFrameAllocator, head: u64) -> Option<u64> {
+        let frame: PhysFrame = allocator.allocate_frame()?;
+        let addr = frame.start_address().as_u64();
+        unsafe { (physical_memory_offset() + addr).as_mut_ptr::<u64>().write(head) };
+        Some(addr)
+    }
+
+    let mut allocator = test_frame_allocator();
+    let free_before = allocator.free_frames();
+    assert!(free_before > PRESSURE_THRESHOLD_FRAMES + 8);
+    fill_cache(&mut allocator);
+    register_pressure_callback(evict).unwrap();
+
+    // Crossing the threshold evicts the cache once.
+    let mut head = END;
+    while allocator.free_frames() >= PRESSURE_THRESHOLD_FRAMES {
+        head = push(&mut allocator, head).unwrap();
+    }
+    assert_eq!(EVICTED.load(Ordering::Relaxed), 4);
+
+    // Running out evicts it again instead of failing.
+    fill_cache(&mut allocator);
+    while allocator.free_frames() > 0 {
+        head = push(&mut allocator, head).unwrap();
+    }
+    head = push(&mut allocator, head).unwrap();
+    assert_eq!(EVICTED.load(Ordering::Relaxed), 8);
+
+    unregister_pressure_callback(evict).unwrap();
+    while head != END {
+        let next = unsafe { (physical_memory_offset() + head).as_ptr::<u64>().read() };
+        let frame: PhysFrame = PhysFrame::containing_address(PhysAddr::new(head));
+        unsafe { allocator.deallocate_frame(frame) };
+        head = next;
+    }
+    assert_eq!(allocator.free_frames(), free_before);
+}
+
+#[cfg(test)]
+fn map_test_range(pages: PageRange, mapper: &mut OffsetPageTable, allocator: &mut BitmapFrameAllocator) {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    for page in pages {
+        let frame = allocator.allocate_frame().unwrap();
+        unsafe { mapper.map_to(page, frame, flags, allocator) }.unwrap().flush();
+        // Touch the page so its translation is cached.
+        unsafe { page.start_address().as_mut_ptr::<u64>().write_volatile(0) };
+    }
+}
+
+#[test_case]
+fn test_unmap_range_leaves_no_stale_entries() {
+    use x86_64::structures::paging::mapper::{Translate, TranslateResult};
+
+    let mut mapper = unsafe { init(physical_memory_offset()) };
+    let mut allocator = test_frame_allocator();
+    let start: Page = Page::containing_address(VirtAddr::new(0x5000_0000_0000));
+    let pages = Page::range(start, start + FULL_FLUSH_THRESHOLD as u64 * 2);
+
+    map_test_range(pages, &mut mapper, &mut allocator);
+    let first: *mut u64 = start.start_address().as_mut_ptr();
+    unsafe { first.write_volatile(0xAAAA) };
+    let fresh = allocator.allocate_zeroed_frame().unwrap();
+    // The frames all came from `allocator` in `map_test_range`.
+    assert_eq!(unsafe { unmap_range(pages, &mut mapper, &mut allocator) }, Ok(pages.count()));
+    for page in pages {
+        assert!(matches!(mapper.translate(page.start_address()), TranslateResult::NotMapped));
+    }
+
+    // Map the first page to a fresh frame without flushing. A stale entry
+    // would still point at the old frame and read 0xAAAA.
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe { mapper.map_to(start, fresh, flags, &mut *allocator) }.unwrap().ignore();
+    assert_eq!(unsafe { first.read_volatile() }, 0);
+
+    assert_eq!(unsafe { unmap_range(pages, &mut mapper, &mut allocator) }, Ok(1));
+}
+
+#[cfg(feature = "bench")]
+#[test_case]
+fn bench_unmap_10000_pages() {
+    use crate::bench::rdtsc;
+
+    const PAGES: u64 = 10_000;
+
+    let mut mapper = unsafe { init(physical_memory_offset()) };
+    let mut allocator = test_frame_allocator();
+    let start: Page = Page::containing_address(VirtAddr::new(0x5100_0000_0000));
+    let pages = Page::range(start, start + PAGES);
+
+    let mut cycles = [0u64; 2];
+    for (batched, cycles) in [false, true].into_iter().zip(cycles.iter_mut()) {
+        map_test_range(pages, &mut mapper, &mut allocator);
+        let begin = rdtsc();
+        let unmapped = unsafe { unmap_pages(pages, &mut mapper, &mut allocator, batched) }.unwrap();
+        *cycles = rdtsc() - begin;
+        assert_eq!(unmapped, PAGES as usize);
     }
+    crate::serial_println!("bench unmap {} pages: invlpg {} / batched {} cycles",
+        PAGES, cycles[0], cycles[1]);
 }
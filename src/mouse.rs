@@ -0,0 +1,130 @@
+use spin::Mutex;
+use crate::error::KernelResult;
+use crate::interrupts::{self, InterruptIndex};
+use crate::ps2;
+
+const SET_DEFAULTS: u8 = 0xF6;
+const ENABLE_REPORTING: u8 = 0xF4;
+
+const LEFT_BUTTON: u8 = 1 << 0;
+const RIGHT_BUTTON: u8 = 1 << 1;
+const MIDDLE_BUTTON: u8 = 1 << 2;
+const ALWAYS_ONE: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+// Movement is relative; `dy` grows downwards to match screen rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub dx: i16,
+    pub dy: i16,
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+pub struct MouseDecoder {
+    packet: [u8; 3],
+    len: usize,
+}
+
+impl MouseDecoder {
+    pub const fn new() -> Self {
+        MouseDecoder { packet: [0; 3], len: 0 }
+    }
+
+    pub fn add_byte(&mut self, byte: u8) -> Option<MouseEvent> {
+        // Bit 3 is always set in the first byte of a packet; anything else
+        // there means we lost sync, so drop bytes until a header shows up.
+        if self.len == 0 && byte & ALWAYS_ONE == 0 {
+            return None;
+        }
+        self.packet[self.len] = byte;
+        self.len += 1;
+        if self.len < self.packet.len() {
+            return None;
+        }
+        self.len = 0;
+
+        let [flags, x, y] = self.packet;
+        if flags & (X_OVERFLOW | Y_OVERFLOW) != 0 {
+            return None;
+        }
+        Some(MouseEvent {
+            dx: sign_extend(x, flags & X_SIGN != 0),
+            dy: -sign_extend(y, flags & Y_SIGN != 0),
+            left: flags & LEFT_BUTTON != 0,
+            right: flags & RIGHT_BUTTON != 0,
+            middle: flags & MIDDLE_BUTTON != 0,
+        })
+    }
+}
+
+impl Default for MouseDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Deltas are 9-bit two's complement with the sign bit in the flags byte.
+fn sign_extend(value: u8, negative: bool) -> i16 {
+    if negative {
+        i16::from(value) - 0x100
+    } else {
+        i16::from(value)
+    }
+}
+
+static DECODER: Mutex<MouseDecoder> = Mutex::new(MouseDecoder::new());
+static CALLBACK: Mutex<Option<fn(MouseEvent)>> = Mutex::new(None);
+
+pub fn init() -> KernelResult<()> {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    without_interrupts(|| {
+        ps2::enable_aux()?;
+        let config = ps2::read_config()?;
+        ps2::write_config((config | ps2::CONFIG_AUX_INTERRUPT) & !ps2::CONFIG_AUX_CLOCK_DISABLED)?;
+        ps2::send_aux_byte(SET_DEFAULTS)?;
+        ps2::send_aux_byte(ENABLE_REPORTING)
+    })?;
+    interrupts::unmask(InterruptIndex::Mouse);
+    Ok(())
+}
+
+// The callback runs in interrupt context.
+pub fn set_callback(callback: fn(MouseEvent)) {
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    without_interrupts(|| *CALLBACK.lock() = Some(callback));
+}
+
+pub fn add_byte(byte: u8) {
+    let event = DECODER.lock().add_byte(byte);
+    let callback = *CALLBACK.lock();
+    if let (Some(event), Some(callback)) = (event, callback) {
+        callback(event);
+    }
+}
+
+#[test_case]
+fn test_decode_packet_and_resync() {
+    let mut decoder = MouseDecoder::new();
+
+    // Left button held, moved 5 right and 3 down (PS/2 reports up as positive).
+    assert_eq!(decoder.add_byte(0x29), None);
+    assert_eq!(decoder.add_byte(0x05), None);
+    assert_eq!(decoder.add_byte(0xfd), Some(MouseEvent {
+        dx: 5, dy: 3, left: true, right: false, middle: false,
+    }));
+
+    // A stray byte without the always-one bit is skipped.
+    assert_eq!(decoder.add_byte(0x00), None);
+    assert_eq!(decoder.add_byte(0x1a), None);
+    assert_eq!(decoder.add_byte(0xf0), None);
+    assert_eq!(decoder.add_byte(0x10), Some(MouseEvent {
+        dx: -16, dy: -16, left: false, right: true, middle: false,
+    }));
+}
@@ -0,0 +1,87 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::cmos::RebootReason;
+use crate::{exit_qemu, exit_token, hlt_loop, reboot, QemuExitCode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicBehavior {
+    Halt,
+    Reboot,
+    QemuExit(QemuExitCode),
+}
+
+impl PanicBehavior {
+    // Names accepted by the `panic=` command line option.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "halt" => Some(PanicBehavior::Halt),
+            "reboot" => Some(PanicBehavior::Reboot),
+            "qemu-exit" => Some(PanicBehavior::QemuExit(QemuExitCode::Failed)),
+            _ => None,
+        }
+    }
+
+    // Packed into a single word so the panic handler can read it without
+    // taking a lock: the low byte is the variant, the rest the exit code.
+    const fn encode(self) -> u32 {
+        match self {
+            PanicBehavior::Halt => 0,
+            PanicBehavior::Reboot => 1,
+            PanicBehavior::QemuExit(code) => 2 | (code as u32) << 8,
+        }
+    }
+
+    fn decode(value: u32) -> Self {
+        match value & 0xff {
+            1 => PanicBehavior::Reboot,
+            2 if value >> 8 == QemuExitCode::Success as u32 => {
+                PanicBehavior::QemuExit(QemuExitCode::Success)
+            }
+            2 => PanicBehavior::QemuExit(QemuExitCode::Failed),
+            _ => PanicBehavior::Halt,
+        }
+    }
+}
+
+const DEFAULT: PanicBehavior = if cfg!(test) {
+    PanicBehavior::QemuExit(QemuExitCode::Failed)
+} else {
+    PanicBehavior::Halt
+};
+
+static BEHAVIOR: AtomicU32 = AtomicU32::new(DEFAULT.encode());
+
+pub fn set_behavior(behavior: PanicBehavior) {
+    BEHAVIOR.store(behavior.encode(), Ordering::Relaxed);
+}
+
+pub fn behavior() -> PanicBehavior {
+    PanicBehavior::decode(BEHAVIOR.load(Ordering::Relaxed))
+}
+
+// Called by panic handlers once the message has been printed.
+pub fn finish() -> ! {
+    match behavior() {
+        PanicBehavior::Halt => hlt_loop(),
+        PanicBehavior::Reboot => reboot(RebootReason::Panic),
+        PanicBehavior::QemuExit(code) => {
+            exit_qemu(exit_token(), code);
+            hlt_loop();
+        }
+    }
+}
+
+#[test_case]
+fn test_behavior_round_trips() {
+    assert_eq!(behavior(), PanicBehavior::QemuExit(QemuExitCode::Failed));
+    for behavior_to_set in [
+        PanicBehavior::Reboot,
+        PanicBehavior::Halt,
+        PanicBehavior::QemuExit(QemuExitCode::Success),
+        PanicBehavior::QemuExit(QemuExitCode::Failed),
+    ] {
+        set_behavior(behavior_to_set);
+        assert_eq!(behavior(), behavior_to_set);
+    }
+    assert_eq!(PanicBehavior::from_name("reboot"), Some(PanicBehavior::Reboot));
+    assert_eq!(PanicBehavior::from_name("explode"), None);
+}
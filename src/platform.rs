@@ -0,0 +1,53 @@
+use core::arch::x86_64::__cpuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HypervisorVendor {
+    // QEMU without acceleration.
+    Tcg,
+    Kvm,
+    VMware,
+    HyperV,
+    Xen,
+    VirtualBox,
+    Other([u8; 12]),
+}
+
+impl HypervisorVendor {
+    fn from_signature(signature: [u8; 12]) -> Self {
+        match &signature {
+            b"TCGTCGTCGTCG" => HypervisorVendor::Tcg,
+            b"KVMKVMKVM\0\0\0" => HypervisorVendor::Kvm,
+            b"VMwareVMware" => HypervisorVendor::VMware,
+            b"Microsoft Hv" => HypervisorVendor::HyperV,
+            b"XenVMMXenVMM" => HypervisorVendor::Xen,
+            b"VBoxVBoxVBox" => HypervisorVendor::VirtualBox,
+            _ => HypervisorVendor::Other(signature),
+        }
+    }
+}
+
+// CPUID.01H:ECX[31] is reserved as zero on real CPUs and set by every
+// hypervisor, which then describes itself at leaf 0x40000000.
+pub fn hypervisor() -> Option<HypervisorVendor> {
+    if __cpuid(1).ecx & (1 << 31) == 0 {
+        return None;
+    }
+    let leaf = __cpuid(0x4000_0000);
+    let mut signature = [0u8; 12];
+    signature[0..4].copy_from_slice(&leaf.ebx.to_le_bytes());
+    signature[4..8].copy_from_slice(&leaf.ecx.to_le_bytes());
+    signature[8..12].copy_from_slice(&leaf.edx.to_le_bytes());
+    Some(HypervisorVendor::from_signature(signature))
+}
+
+// KVM is also reported for other KVM front ends, but for this kernel it
+// means QEMU in practice.
+pub fn is_qemu() -> bool {
+    matches!(hypervisor(), Some(HypervisorVendor::Tcg | HypervisorVendor::Kvm))
+}
+
+#[test_case]
+fn test_detects_qemu() {
+    assert!(is_qemu());
+    assert!(matches!(hypervisor(), Some(HypervisorVendor::Tcg | HypervisorVendor::Kvm)));
+}
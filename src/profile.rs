@@ -0,0 +1,133 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use crate::serial_println;
+
+// Samples are counted per 64-byte block of code.
+const BUCKET_SHIFT: u32 = 6;
+const SLOTS: usize = 1024;
+const MAX_PROBES: usize = 16;
+const REPORT_ROWS: usize = 10;
+// Keys are the bucket number shifted left once, with the low bit set for
+// samples taken in ring 3. Zero marks an empty slot, so keys are stored + 1.
+const USER_BIT: u64 = 1;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static KEYS: [AtomicU64; SLOTS] = [const { AtomicU64::new(0) }; SLOTS];
+static COUNTS: [AtomicU32; SLOTS] = [const { AtomicU32::new(0) }; SLOTS];
+static SAMPLES: AtomicU32 = AtomicU32::new(0);
+// Samples that found no free slot; reported so a skewed histogram shows.
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub start: u64,
+    pub user: bool,
+    pub count: u32,
+}
+
+impl Sample {
+    pub fn end(&self) -> u64 {
+        self.start + (1 << BUCKET_SHIFT)
+    }
+}
+
+// Clears the histogram and starts sampling on every timer tick.
+pub fn start() {
+    ENABLED.store(false, Ordering::Relaxed);
+    for (key, count) in KEYS.iter().zip(&COUNTS) {
+        key.store(0, Ordering::Relaxed);
+        count.store(0, Ordering::Relaxed);
+    }
+    SAMPLES.store(0, Ordering::Relaxed);
+    DROPPED.store(0, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Release);
+}
+
+pub fn stop() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn total_samples() -> u32 {
+    SAMPLES.load(Ordering::Relaxed)
+}
+
+// Called by the timer interrupt with the interrupted RIP and CS. Costs one
+// load when profiling is off; otherwise a hash and a short probe.
+pub fn record(rip: u64, code_segment: u64) {
+    if !ENABLED.load(Ordering::Acquire) {
+        return;
+    }
+    let user = code_segment & 3 == 3;
+    let key = ((rip >> BUCKET_SHIFT) << 1 | if user { USER_BIT } else { 0 }) + 1;
+    let hash = (key.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 54) as usize;
+    SAMPLES.fetch_add(1, Ordering::Relaxed);
+    for probe in 0..MAX_PROBES {
+        let slot = (hash + probe) % SLOTS;
+        // Only the timer interrupt writes keys, so a load and store suffice.
+        match KEYS[slot].load(Ordering::Relaxed) {
+            0 => KEYS[slot].store(key, Ordering::Relaxed),
+            existing if existing != key => continue,
+            _ => {}
+        }
+        COUNTS[slot].fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn samples() -> impl Iterator<Item = Sample> {
+    KEYS.iter().zip(&COUNTS).filter_map(|(key, count)| {
+        let key = key.load(Ordering::Relaxed).checked_sub(1)?;
+        Some(Sample {
+            start: (key >> 1) << BUCKET_SHIFT,
+            user: key & USER_BIT != 0,
+            count: count.load(Ordering::Relaxed),
+        })
+    })
+}
+
+// Prints the hottest buckets over serial. There is no symbol table yet, so
+// they are shown as address ranges to look up in the kernel ELF.
+pub fn report() {
+    let total = total_samples();
+    serial_println!("profile: {} samples, {} dropped", total, DROPPED.load(Ordering::Relaxed));
+    let mut hottest: [Option<Sample>; REPORT_ROWS] = [None; REPORT_ROWS];
+    for sample in samples() {
+        // Insertion into the sorted top rows; the coldest falls off the end.
+        let Some(row) = hottest.iter().position(|top| top.is_none_or(|top| sample.count > top.count))
+        else {
+            continue;
+        };
+        hottest[row..].rotate_right(1);
+        hottest[row] = Some(sample);
+    }
+    for sample in hottest.into_iter().flatten() {
+        serial_println!("  {:#018x}-{:#018x} {:>6} {:>3}% {}", sample.start, sample.end(),
+            sample.count, u64::from(sample.count) * 100 / u64::from(total.max(1)),
+            if sample.user { "user" } else { "kernel" });
+    }
+}
+
+#[test_case]
+fn test_busy_loop_dominates_profile() {
+    const SAMPLES_WANTED: u32 = 12;
+
+    // The spin is inline assembly so none of the time lands in a callee.
+    #[inline(never)]
+    fn busy() {
+        while total_samples() < SAMPLES_WANTED {
+            unsafe { core::arch::asm!("2: dec {0}", "jnz 2b", inout(reg) 100_000u64 => _) };
+        }
+    }
+
+    start();
+    busy();
+    stop();
+    report();
+
+    let busy = busy as fn() as usize as u64;
+    let inside = samples()
+        .filter(|sample| !sample.user && sample.end() > busy && sample.start < busy + 256)
+        .map(|sample| sample.count)
+        .sum::<u32>();
+    assert!(inside * 4 >= total_samples() * 3, "{} of {} samples in busy()", inside, total_samples());
+}
@@ -0,0 +1,166 @@
+use x86_64::instructions::port::Port;
+use crate::error::{KernelError, KernelResult};
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_AUX: u8 = 0xA7;
+const ENABLE_AUX: u8 = 0xA8;
+const SELF_TEST: u8 = 0xAA;
+const DISABLE_KEYBOARD: u8 = 0xAD;
+const ENABLE_KEYBOARD: u8 = 0xAE;
+const WRITE_AUX: u8 = 0xD4;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+// A device that keeps refilling the output buffer is not waited out forever.
+const FLUSH_LIMIT: usize = 256;
+
+pub const CONFIG_KEYBOARD_INTERRUPT: u8 = 1 << 0;
+pub const CONFIG_AUX_INTERRUPT: u8 = 1 << 1;
+pub const CONFIG_KEYBOARD_CLOCK_DISABLED: u8 = 1 << 4;
+pub const CONFIG_AUX_CLOCK_DISABLED: u8 = 1 << 5;
+// The controller translates scancode set 2 from the keyboard into set 1.
+pub const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const ACK: u8 = 0xFA;
+const RESEND: u8 = 0xFE;
+
+const TIMEOUT: usize = 100_000;
+const RETRIES: usize = 3;
+
+fn status() -> u8 {
+    unsafe { Port::new(STATUS_PORT).read() }
+}
+
+// A missing or wedged controller never clears its flags, so every wait is
+// bounded and reported as a missing device.
+fn wait_for(ready: impl Fn(u8) -> bool) -> KernelResult<()> {
+    for _ in 0..TIMEOUT {
+        if ready(status()) {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(KernelError::DeviceNotFound)
+}
+
+pub fn write_command(command: u8) -> KernelResult<()> {
+    wait_for(|status| status & STATUS_INPUT_FULL == 0)?;
+    unsafe { Port::new(STATUS_PORT).write(command) };
+    Ok(())
+}
+
+pub fn write_data(byte: u8) -> KernelResult<()> {
+    wait_for(|status| status & STATUS_INPUT_FULL == 0)?;
+    unsafe { Port::new(DATA_PORT).write(byte) };
+    Ok(())
+}
+
+pub fn read_data() -> KernelResult<u8> {
+    wait_for(|status| status & STATUS_OUTPUT_FULL != 0)?;
+    Ok(unsafe { Port::new(DATA_PORT).read() })
+}
+
+// The waiting byte, if there is one. IRQ 1 and 12 are edge-latched, so they
+// can fire after a command sequence has already read the byte that raised
+// them; handlers use this rather than reading an empty buffer.
+pub fn try_read_data() -> Option<u8> {
+    if status() & STATUS_OUTPUT_FULL == 0 {
+        return None;
+    }
+    Some(unsafe { Port::new(DATA_PORT).read() })
+}
+
+// Discards whatever is waiting in the output buffer. Returns the number of
+// bytes dropped.
+pub fn flush_output() -> usize {
+    let mut dropped = 0;
+    while dropped < FLUSH_LIMIT && status() & STATUS_OUTPUT_FULL != 0 {
+        let _: u8 = unsafe { Port::new(DATA_PORT).read() };
+        dropped += 1;
+    }
+    dropped
+}
+
+// Brings a wedged controller back: both ports are disabled and the output
+// buffer emptied, the controller self-tests, and then the configuration is
+// restored with the keyboard port's clock and interrupt forced on. The aux
+// port is re-enabled only if it was enabled before. Some controllers reset
+// the configuration during the self-test, hence the rewrite.
+pub fn reset_controller() -> KernelResult<()> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        write_command(DISABLE_KEYBOARD)?;
+        write_command(DISABLE_AUX)?;
+        flush_output();
+        let config = read_config()?;
+        let passed = (0..RETRIES).any(|_| {
+            write_command(SELF_TEST).and_then(|()| read_data()) == Ok(SELF_TEST_PASSED)
+        });
+        if !passed {
+            return Err(KernelError::IoError);
+        }
+        let config = (config | CONFIG_KEYBOARD_INTERRUPT) & !CONFIG_KEYBOARD_CLOCK_DISABLED;
+        write_config(config)?;
+        write_command(ENABLE_KEYBOARD)?;
+        if config & CONFIG_AUX_CLOCK_DISABLED == 0 {
+            enable_aux()?;
+        }
+        flush_output();
+        Ok(())
+    })
+}
+
+// Turns the auxiliary (mouse) port on. Its clock and interrupt are set
+// separately, in the configuration byte.
+pub fn enable_aux() -> KernelResult<()> {
+    write_command(ENABLE_AUX)
+}
+
+// The controller configuration byte. Callers disable interrupts so the
+// keyboard cannot slip a scancode in before the reply.
+pub fn read_config() -> KernelResult<u8> {
+    write_command(READ_CONFIG)?;
+    read_data()
+}
+
+pub fn write_config(config: u8) -> KernelResult<()> {
+    write_command(WRITE_CONFIG)?;
+    write_data(config)
+}
+
+// Sends a byte to the keyboard and waits for its acknowledgement. Interrupts
+// are disabled so the IRQ 1 handler does not consume the reply.
+pub fn send_keyboard_byte(byte: u8) -> KernelResult<()> {
+    send_device_byte(None, byte)
+}
+
+// Same as `send_keyboard_byte`, for the auxiliary (mouse) port.
+pub fn send_aux_byte(byte: u8) -> KernelResult<()> {
+    send_device_byte(Some(WRITE_AUX), byte)
+}
+
+fn send_device_byte(prefix: Option<u8>, byte: u8) -> KernelResult<()> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        for _ in 0..RETRIES {
+            if let Some(command) = prefix {
+                write_command(command)?;
+            }
+            write_data(byte)?;
+            match read_data()? {
+                ACK => return Ok(()),
+                RESEND => continue,
+                _ => return Err(KernelError::DeviceNotFound),
+            }
+        }
+        Err(KernelError::DeviceNotFound)
+    })
+}
@@ -0,0 +1,59 @@
+use crate::bench::rdtsc;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::instructions::random::RdRand;
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+// Set by `randseed=`: `random_u64` then runs SplitMix64 from `STATE` instead
+// of reading hardware sources.
+static SEEDED: AtomicBool = AtomicBool::new(false);
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+// Hardware randomness, if the CPU has RDRAND and it delivers.
+pub fn rdrand() -> Option<u64> {
+    let rdrand = RdRand::new()?;
+    // The instruction can fail transiently when the DRNG is drained.
+    (0..10).find_map(|_| rdrand.get_u64())
+}
+
+// SplitMix64 finalizer: spreads the low-entropy bits of its input over the
+// whole word.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(GOLDEN_GAMMA);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// Makes every later `random_u64` call reproducible. Must run before the
+// first consumer, e.g. `exit_token`, caches a value.
+pub fn set_seed(seed: u64) {
+    STATE.store(seed, Ordering::Relaxed);
+    SEEDED.store(true, Ordering::Relaxed);
+}
+
+// The seeded sequence if `set_seed` was called, else RDRAND if available,
+// otherwise the mixed TSC. Not for cryptography: the fallback is only as
+// unpredictable as boot timing.
+pub fn random_u64() -> u64 {
+    if SEEDED.load(Ordering::Relaxed) {
+        return mix(STATE.fetch_add(GOLDEN_GAMMA, Ordering::Relaxed));
+    }
+    rdrand().unwrap_or_else(|| mix(rdtsc()))
+}
+
+#[test_case]
+fn test_random_values_differ() {
+    assert_ne!(random_u64(), random_u64());
+    assert_ne!(mix(1), mix(2));
+}
+
+#[test_case]
+fn test_seed_repeats_sequence() {
+    set_seed(42);
+    let first = [random_u64(), random_u64()];
+    set_seed(42);
+    assert_eq!([random_u64(), random_u64()], first);
+    assert_ne!(first[0], first[1]);
+    SEEDED.store(false, Ordering::Relaxed);
+}
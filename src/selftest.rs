@@ -0,0 +1,110 @@
+use crate::error::{KernelError, KernelResult};
+use crate::memory::{self, BitmapFrameAllocator};
+use crate::{cmos, interrupts, serial, serial_println};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame,
+};
+use x86_64::VirtAddr;
+
+// An otherwise unused address for the mapping check.
+const SCRATCH_PAGE: u64 = 0x5200_0000_0000;
+const TICK_TIMEOUT_CYCLES: u64 = 10_000_000_000;
+
+pub struct Resources<'a> {
+    pub mapper: &'a mut OffsetPageTable<'static>,
+    pub frame_allocator: &'a mut BitmapFrameAllocator,
+}
+
+struct Check {
+    name: &'static str,
+    run: fn(&mut Resources) -> KernelResult<()>,
+}
+
+const CHECKS: [Check; 5] = [
+    Check { name: "frame allocator", run: check_frame_allocator },
+    Check { name: "page mapping", run: check_page_mapping },
+    Check { name: "rtc", run: check_rtc },
+    Check { name: "serial loopback", run: check_serial_loopback },
+    Check { name: "timer ticks", run: check_timer_ticks },
+];
+
+fn check_frame_allocator(resources: &mut Resources) -> KernelResult<()> {
+    let free = resources.frame_allocator.free_frames();
+    let frame: PhysFrame = resources.frame_allocator.allocate_frame()
+        .ok_or(KernelError::OutOfMemory)?;
+    unsafe { resources.frame_allocator.deallocate_frame(frame) };
+    if resources.frame_allocator.free_frames() != free {
+        return Err(KernelError::OutOfMemory);
+    }
+    Ok(())
+}
+
+fn check_page_mapping(resources: &mut Resources) -> KernelResult<()> {
+    let page: Page = Page::containing_address(VirtAddr::new(SCRATCH_PAGE));
+    let frame = resources.frame_allocator.allocate_zeroed_frame()
+        .ok_or(KernelError::OutOfMemory)?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe { resources.mapper.map_to(page, frame, flags, &mut *resources.frame_allocator) }
+        .map_err(|_| KernelError::MappingFailed)?
+        .flush();
+    let ptr = page.start_address().as_mut_ptr::<u64>();
+    let value = unsafe {
+        ptr.write_volatile(0x5e1f_7e57);
+        ptr.read_volatile()
+    };
+    // The only page in the range maps the frame allocated above.
+    let unmapped = unsafe {
+        memory::unmap_range(Page::range(page, page + 1), resources.mapper, resources.frame_allocator)
+    }?;
+    if value != 0x5e1f_7e57 || unmapped != 1 {
+        return Err(KernelError::MappingFailed);
+    }
+    Ok(())
+}
+
+fn check_rtc(_resources: &mut Resources) -> KernelResult<()> {
+    let (hours, minutes, seconds) = cmos::rtc_time();
+    if hours < 24 && minutes < 60 && seconds < 60 {
+        Ok(())
+    } else {
+        Err(KernelError::IoError)
+    }
+}
+
+fn check_serial_loopback(_resources: &mut Resources) -> KernelResult<()> {
+    serial::loopback_test()
+}
+
+fn check_timer_ticks(_resources: &mut Resources) -> KernelResult<()> {
+    use crate::bench::rdtsc;
+
+    // Poll instead of waiting for the IRQ, so a dead timer is a failure
+    // rather than a hang.
+    let timer = interrupts::InterruptIndex::Timer as u8;
+    let ticks = interrupts::irq_sequence(timer);
+    let start = rdtsc();
+    while interrupts::irq_sequence(timer) == ticks {
+        if rdtsc() - start > TICK_TIMEOUT_CYCLES {
+            return Err(KernelError::Timeout);
+        }
+        core::hint::spin_loop();
+    }
+    Ok(())
+}
+
+// Runs every check, even after a failure, and prints one line each plus a
+// summary over serial. Returns the number of failed checks.
+pub fn run(resources: &mut Resources) -> usize {
+    let mut failed = 0;
+    for check in &CHECKS {
+        match (check.run)(resources) {
+            Ok(()) => serial_println!("selftest {}... [ok]", check.name),
+            Err(err) => {
+                serial_println!("selftest {}... [failed] {}", check.name, err);
+                failed += 1;
+            }
+        }
+    }
+    serial_println!("selftest: {}/{} passed", CHECKS.len() - failed, CHECKS.len());
+    failed
+}
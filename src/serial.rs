@@ -1,25 +1,252 @@
 use uart_16550::SerialPort;
 use spin::Mutex;
 use lazy_static::lazy_static;
+use x86_64::instructions::port::Port;
+use crate::collections::RingBuffer;
+use crate::error::{KernelError, KernelResult};
+use crate::sync::{level, OrderedMutex};
+
+const COM1: u16 = 0x3F8;
+const TX_QUEUE_SIZE: usize = 4096;
+const FIFO_SIZE: usize = 16;
+
+const IER_THR_EMPTY: u8 = 1 << 1;
+const FCR_ENABLE_AND_CLEAR: u8 = 0xC7;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+const MCR_LOOPBACK: u8 = 1 << 4;
 
 lazy_static! {
     pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+        let mut serial_port = unsafe { SerialPort::new(COM1) };
         serial_port.init();
+        unsafe {
+            // Only the transmit interrupt is used, and only while bytes are queued.
+            Port::new(COM1 + 1).write(0u8);
+            Port::new(COM1 + 2).write(FCR_ENABLE_AND_CLEAR);
+        }
         Mutex::new(serial_port)
     };
 }
 
+static TX_QUEUE: OrderedMutex<TxQueue> =
+    OrderedMutex::new("serial tx queue", level::SERIAL_TX, TxQueue::new());
+
+struct TxQueue {
+    bytes: RingBuffer<u8, TX_QUEUE_SIZE>,
+}
+
+impl TxQueue {
+    const fn new() -> Self {
+        TxQueue { bytes: RingBuffer::new() }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.bytes.is_full() {
+            // Full: make room by sending the oldest byte synchronously
+            // rather than dropping log output.
+            wait_for_thr_empty();
+            send_next(self);
+        }
+        let _ = self.bytes.push(byte);
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        self.bytes.pop()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl core::fmt::Write for TxQueue {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            self.push(byte);
+        }
+        Ok(())
+    }
+}
+
+fn thr_empty() -> bool {
+    let line_status: u8 = unsafe { Port::new(COM1 + 5).read() };
+    line_status & LSR_THR_EMPTY != 0
+}
+
+fn wait_for_thr_empty() {
+    while !thr_empty() {
+        core::hint::spin_loop();
+    }
+}
+
+fn send_next(queue: &mut TxQueue) -> bool {
+    match queue.pop() {
+        Some(byte) => {
+            unsafe { Port::new(COM1).write(byte) };
+            true
+        }
+        None => false,
+    }
+}
+
+fn set_tx_interrupt(enabled: bool) {
+    let value = if enabled { IER_THR_EMPTY } else { 0 };
+    unsafe { Port::new(COM1 + 1).write(value) };
+}
+
+// Called from the COM1 interrupt: refill the empty transmit FIFO and stop
+// the interrupt once the queue has drained.
+pub fn handle_interrupt() {
+    let mut queue = TX_QUEUE.lock();
+    // Reading the interrupt identification register acknowledges THRE.
+    let _iir: u8 = unsafe { Port::new(COM1 + 2).read() };
+    if thr_empty() {
+        for _ in 0..FIFO_SIZE {
+            if !send_next(&mut queue) {
+                break;
+            }
+        }
+    }
+    set_tx_interrupt(!queue.is_empty());
+}
+
+pub fn flush() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut queue = TX_QUEUE.lock();
+        while !queue.is_empty() {
+            wait_for_thr_empty();
+            send_next(&mut queue);
+        }
+        set_tx_interrupt(false);
+    });
+}
+
+// Sends a byte through the UART's internal loopback and checks that it comes
+// back. Queued output is flushed first and nothing leaves the port.
+pub fn loopback_test() -> KernelResult<()> {
+    use x86_64::instructions::interrupts;
+
+    flush();
+    interrupts::without_interrupts(|| {
+        let _queue = TX_QUEUE.lock();
+        let mut modem_control: Port<u8> = Port::new(COM1 + 4);
+        let mut line_status: Port<u8> = Port::new(COM1 + 5);
+        let mut data: Port<u8> = Port::new(COM1);
+        unsafe {
+            let saved = modem_control.read();
+            modem_control.write(saved | MCR_LOOPBACK);
+            // Drop anything already received.
+            while line_status.read() & LSR_DATA_READY != 0 {
+                data.read();
+            }
+            data.write(0xA5);
+            let received = (0..100_000).find_map(|_| {
+                (line_status.read() & LSR_DATA_READY != 0).then(|| data.read())
+            });
+            modem_control.write(saved);
+            match received {
+                Some(0xA5) => Ok(()),
+                Some(_) => Err(KernelError::IoError),
+                None => Err(KernelError::Timeout),
+            }
+        }
+    })
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
     use x86_64::instructions::interrupts;
-    
+
+    lazy_static::initialize(&SERIAL1);
     interrupts::without_interrupts(|| {
-        SERIAL1.lock().write_fmt(args).expect("Printing to serial failed");
+        let mut queue = TX_QUEUE.lock();
+        queue.write_fmt(args).expect("Printing to serial failed");
+        set_tx_interrupt(true);
     });
 }
 
+// Like `_print`, but drops the output instead of spinning when the queue is
+// held, for `try_print!` on a serial console.
+#[doc(hidden)]
+pub fn _try_print(args: ::core::fmt::Arguments) -> KernelResult<()> {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    lazy_static::initialize(&SERIAL1);
+    interrupts::without_interrupts(|| {
+        let mut queue = TX_QUEUE.try_lock().ok_or(KernelError::WouldBlock)?;
+        queue.write_fmt(args).map_err(|_| KernelError::IoError)?;
+        set_tx_interrupt(true);
+        Ok(())
+    })
+}
+
+// A `fmt::Write` sink for the serial port, for code that writes piecewise
+// (e.g. `serialize::json::JsonWriter`).
+pub struct SerialWriter;
+
+impl core::fmt::Write for SerialWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        _print(format_args!("{}", s));
+        Ok(())
+    }
+}
+
+const ERROR_MARKER: &str = "[E] ";
+
+// Writes straight to the UART, bypassing the queue and every lock, so it
+// works from panic and exception handlers that may have interrupted a
+// writer.
+struct DirectWriter;
+
+impl core::fmt::Write for DirectWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            wait_for_thr_empty();
+            unsafe { Port::new(COM1).write(byte) };
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: ::core::fmt::Arguments) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    lazy_static::initialize(&SERIAL1);
+    interrupts::without_interrupts(|| {
+        // Send what is already queued first to keep the log in order, unless
+        // the context we interrupted is holding the queue.
+        if let Some(mut queue) = TX_QUEUE.try_lock() {
+            while !queue.is_empty() {
+                wait_for_thr_empty();
+                send_next(&mut queue);
+            }
+        }
+        let _ = DirectWriter.write_str(ERROR_MARKER);
+        let _ = DirectWriter.write_fmt(args);
+    });
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => {
+        $crate::serial::_eprint(format_args!($($arg)*));
+    };
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::serial::_eprint(format_args!("\n")));
+    ($fmt:expr) => ($crate::serial::_eprint(format_args!(concat!($fmt, "\n"))));
+    ($fmt:expr, $($arg:tt)*) => ($crate::serial::_eprint(format_args!(concat!($fmt, "\n"), $($arg)*)));
+}
+
 #[macro_export]
 macro_rules! serial_print {
     ($($arg:tt)*) => {
@@ -33,3 +260,41 @@ macro_rules! serial_println {
     ($fmt:expr) => ($crate::serial::_print(format_args!(concat!($fmt, "\n"))));
     ($fmt:expr, $($arg:tt)*) => ($crate::serial::_print(format_args!(concat!($fmt, "\n"), $($arg)*)));
 }
+
+#[test_case]
+fn test_eprintln_from_exception_handler_with_queue_locked() {
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+    use crate::interrupts::{self, Exception, IdtBuilder};
+
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    extern "x86-interrupt" fn breakpoint_handler(
+        _stack_frame: InterruptStackFrame)
+    {
+        crate::eprintln!("eprintln from breakpoint handler");
+        DONE.store(true, Ordering::Relaxed);
+    }
+
+    lazy_static! {
+        static ref TEST_IDT: InterruptDescriptorTable = {
+            let mut idt = IdtBuilder::new();
+            idt.set_exception(Exception::BREAKPOINT, breakpoint_handler);
+            idt.build()
+        };
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let _queue = TX_QUEUE.lock();
+        TEST_IDT.load();
+        x86_64::instructions::interrupts::int3();
+        interrupts::init_idt();
+    });
+    assert!(DONE.load(Ordering::Relaxed));
+}
+
+#[test_case]
+fn test_loopback() {
+    assert_eq!(loopback_test(), Ok(()));
+    serial_println!("output still works after loopback");
+}
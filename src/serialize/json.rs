@@ -0,0 +1,165 @@
+use core::fmt::{self, Write};
+
+// Nesting deeper than this is a caller bug; the writer panics.
+const MAX_DEPTH: u32 = 64;
+
+// Streams JSON into any `fmt::Write` sink without allocating. The caller is
+// responsible for pairing `begin_*`/`end_*` and for calling `key` before
+// each value inside an object; the writer only inserts the commas.
+pub struct JsonWriter<W: Write> {
+    out: W,
+    depth: u32,
+    // Bit n is set once the container at depth n has an element.
+    non_empty: u64,
+    after_key: bool,
+}
+
+impl<W: Write> JsonWriter<W> {
+    pub fn new(out: W) -> Self {
+        JsonWriter { out, depth: 0, non_empty: 0, after_key: false }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    // Writes the separator owed before the next element of the current
+    // container.
+    fn element(&mut self) -> fmt::Result {
+        if self.after_key {
+            self.after_key = false;
+            return Ok(());
+        }
+        if self.depth > 0 {
+            let bit = 1 << (self.depth - 1);
+            if self.non_empty & bit != 0 {
+                self.out.write_char(',')?;
+            }
+            self.non_empty |= bit;
+        }
+        Ok(())
+    }
+
+    fn begin(&mut self, open: char) -> fmt::Result {
+        self.element()?;
+        assert!(self.depth < MAX_DEPTH, "JSON nested too deeply");
+        self.depth += 1;
+        self.non_empty &= !(1 << (self.depth - 1));
+        self.out.write_char(open)
+    }
+
+    fn end(&mut self, close: char) -> fmt::Result {
+        self.depth -= 1;
+        self.out.write_char(close)
+    }
+
+    pub fn begin_object(&mut self) -> fmt::Result {
+        self.begin('{')
+    }
+
+    pub fn end_object(&mut self) -> fmt::Result {
+        self.end('}')
+    }
+
+    pub fn begin_array(&mut self) -> fmt::Result {
+        self.begin('[')
+    }
+
+    pub fn end_array(&mut self) -> fmt::Result {
+        self.end(']')
+    }
+
+    pub fn key(&mut self, key: &str) -> fmt::Result {
+        self.element()?;
+        self.write_escaped(key)?;
+        self.out.write_char(':')?;
+        self.after_key = true;
+        Ok(())
+    }
+
+    pub fn string(&mut self, value: &str) -> fmt::Result {
+        self.element()?;
+        self.write_escaped(value)
+    }
+
+    pub fn uint(&mut self, value: u64) -> fmt::Result {
+        self.element()?;
+        write!(self.out, "{}", value)
+    }
+
+    pub fn int(&mut self, value: i64) -> fmt::Result {
+        self.element()?;
+        write!(self.out, "{}", value)
+    }
+
+    pub fn boolean(&mut self, value: bool) -> fmt::Result {
+        self.element()?;
+        self.out.write_str(if value { "true" } else { "false" })
+    }
+
+    pub fn null(&mut self) -> fmt::Result {
+        self.element()?;
+        self.out.write_str("null")
+    }
+
+    // Writes any `Display` value as a string, e.g. `format_args!("{:?}", x)`.
+    pub fn display(&mut self, value: impl fmt::Display) -> fmt::Result {
+        self.element()?;
+        self.out.write_char('"')?;
+        write!(Escaper(&mut self.out), "{}", value)?;
+        self.out.write_char('"')
+    }
+
+    fn write_escaped(&mut self, s: &str) -> fmt::Result {
+        self.out.write_char('"')?;
+        Escaper(&mut self.out).write_str(s)?;
+        self.out.write_char('"')
+    }
+}
+
+// Escapes quotes, backslashes and every control character; everything else,
+// including non-ASCII, is passed through as UTF-8.
+struct Escaper<'a, W: Write>(&'a mut W);
+
+impl<W: Write> Write for Escaper<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '"' => self.0.write_str("\\\"")?,
+                '\\' => self.0.write_str("\\\\")?,
+                '\n' => self.0.write_str("\\n")?,
+                '\r' => self.0.write_str("\\r")?,
+                '\t' => self.0.write_str("\\t")?,
+                '\u{0}'..='\u{1f}' | '\u{7f}' => write!(self.0, "\\u{:04x}", c as u32)?,
+                c => self.0.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_escapes_strings() {
+    use crate::test_util::TextBuffer;
+
+    let mut json = JsonWriter::new(TextBuffer::<256>::new());
+    json.begin_object().unwrap();
+    json.key("name").unwrap();
+    json.string("say \"hi\"\n\tbye\\\u{1}").unwrap();
+    json.key("pids").unwrap();
+    json.begin_array().unwrap();
+    json.uint(1).unwrap();
+    json.int(-2).unwrap();
+    json.begin_object().unwrap();
+    json.end_object().unwrap();
+    json.end_array().unwrap();
+    json.key("kind").unwrap();
+    json.display(format_args!("{:?}", Some('"'))).unwrap();
+    json.key("ok").unwrap();
+    json.boolean(true).unwrap();
+    json.end_object().unwrap();
+
+    let buffer = json.into_inner();
+    assert_eq!(buffer.as_str(),
+        r#"{"name":"say \"hi\"\n\tbye\\\u0001","pids":[1,-2,{}],"kind":"Some('\"')","ok":true}"#);
+}
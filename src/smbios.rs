@@ -0,0 +1,247 @@
+use crate::memory;
+use x86_64::VirtAddr;
+
+// The entry point sits on a 16-byte boundary in the BIOS area.
+const SCAN_START: u64 = 0xF0000;
+const SCAN_END: u64 = 0x100000;
+
+const TYPE_BIOS: u8 = 0;
+const TYPE_SYSTEM: u8 = 1;
+const TYPE_MEMORY_DEVICE: u8 = 17;
+const TYPE_END: u8 = 127;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Structure<'a> {
+    pub kind: u8,
+    pub handle: u16,
+    // The formatted area, header included, so offsets match the spec.
+    pub formatted: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> Structure<'a> {
+    pub fn byte(&self, offset: usize) -> Option<u8> {
+        self.formatted.get(offset).copied()
+    }
+
+    pub fn word(&self, offset: usize) -> Option<u16> {
+        let bytes = self.formatted.get(offset..offset + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn dword(&self, offset: usize) -> Option<u32> {
+        let bytes = self.formatted.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    // The string whose 1-based number is stored at `offset`; 0 means none.
+    pub fn string(&self, offset: usize) -> Option<&'a str> {
+        let number = usize::from(self.byte(offset)?);
+        let bytes = self.strings.split(|&byte| byte == 0).nth(number.checked_sub(1)?)?;
+        core::str::from_utf8(bytes).ok().map(str::trim_end).filter(|s| !s.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryDevice<'a> {
+    pub locator: Option<&'a str>,
+    // `None` when the size is unknown; `Some(0)` for an empty slot.
+    pub size_mib: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SystemInfo<'a> {
+    pub bios_vendor: Option<&'a str>,
+    pub bios_version: Option<&'a str>,
+    pub manufacturer: Option<&'a str>,
+    pub product: Option<&'a str>,
+    pub memory_devices: usize,
+    pub memory_mib: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Table<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Table<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Table { data }
+    }
+
+    pub fn structures(&self) -> Structures<'a> {
+        Structures { remaining: self.data }
+    }
+
+    pub fn memory_devices(&self) -> impl Iterator<Item = MemoryDevice<'a>> {
+        self.structures()
+            .filter(|structure| structure.kind == TYPE_MEMORY_DEVICE)
+            .map(|device| MemoryDevice {
+                locator: device.string(0x10),
+                size_mib: memory_device_size(&device),
+            })
+    }
+
+    pub fn system_info(&self) -> SystemInfo<'a> {
+        let mut info = SystemInfo::default();
+        for structure in self.structures() {
+            match structure.kind {
+                TYPE_BIOS => {
+                    info.bios_vendor = structure.string(0x04);
+                    info.bios_version = structure.string(0x05);
+                }
+                TYPE_SYSTEM => {
+                    info.manufacturer = structure.string(0x04);
+                    info.product = structure.string(0x05);
+                }
+                _ => {}
+            }
+        }
+        for device in self.memory_devices() {
+            info.memory_devices += 1;
+            info.memory_mib += device.size_mib.unwrap_or(0);
+        }
+        info
+    }
+}
+
+// 0xffff is unknown, 0x7fff defers to the 32-bit extended size in MiB, and
+// bit 15 otherwise selects KiB instead of MiB.
+fn memory_device_size(device: &Structure) -> Option<u64> {
+    match device.word(0x0C)? {
+        0xffff => None,
+        0x7fff => device.dword(0x1C).map(|size| u64::from(size & 0x7fff_ffff)),
+        size if size & 0x8000 != 0 => Some(u64::from(size & 0x7fff) / 1024),
+        size => Some(u64::from(size)),
+    }
+}
+
+pub struct Structures<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> Iterator for Structures<'a> {
+    type Item = Structure<'a>;
+
+    // Each structure is its formatted area followed by a string table of
+    // NUL-terminated strings, which itself ends with an extra NUL (two NULs
+    // when there are no strings). A truncated or malformed structure ends
+    // the walk.
+    fn next(&mut self) -> Option<Structure<'a>> {
+        let data = self.remaining;
+        let length = usize::from(*data.get(1)?);
+        if length < 4 || length > data.len() {
+            self.remaining = &[];
+            return None;
+        }
+        let Some(end) = data[length..].windows(2).position(|pair| pair == [0, 0]) else {
+            self.remaining = &[];
+            return None;
+        };
+        let structure = Structure {
+            kind: data[0],
+            handle: u16::from_le_bytes([data[2], data[3]]),
+            formatted: &data[..length],
+            strings: &data[length..length + end],
+        };
+        self.remaining = if structure.kind == TYPE_END { &[] } else { &data[length + end + 2..] };
+        Some(structure)
+    }
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+// Returns the physical address and length of the structure table described
+// by an `_SM3_` (64-bit) or `_SM_` (32-bit) entry point, if its checksums
+// hold.
+fn parse_entry_point(bytes: &[u8]) -> Option<(u64, usize)> {
+    if bytes.starts_with(b"_SM3_") {
+        let entry = bytes.get(..usize::from(*bytes.get(6)?))?;
+        if entry.len() < 0x18 || !checksum_ok(entry) {
+            return None;
+        }
+        let length = u32::from_le_bytes(entry[0x0C..0x10].try_into().ok()?);
+        let address = u64::from_le_bytes(entry[0x10..0x18].try_into().ok()?);
+        return Some((address, length as usize));
+    }
+    if bytes.starts_with(b"_SM_") {
+        let entry = bytes.get(..usize::from(*bytes.get(5)?))?;
+        if entry.len() < 0x1F || !checksum_ok(entry) {
+            return None;
+        }
+        // The intermediate `_DMI_` anchor has its own checksum.
+        let dmi = &entry[0x10..0x1F];
+        if !dmi.starts_with(b"_DMI_") || !checksum_ok(dmi) {
+            return None;
+        }
+        let length = u16::from_le_bytes([entry[0x16], entry[0x17]]);
+        let address = u32::from_le_bytes(entry[0x18..0x1C].try_into().ok()?);
+        return Some((u64::from(address), usize::from(length)));
+    }
+    None
+}
+
+fn physical_slice(address: u64, length: usize) -> Option<&'static [u8]> {
+    let start = memory::physical_memory_offset() + address;
+    let end = start + length as u64;
+    let pages = (start.align_down(4096u64).as_u64()..end.as_u64()).step_by(4096);
+    if !pages.map(VirtAddr::new).all(memory::is_mapped) {
+        return None;
+    }
+    Some(unsafe { core::slice::from_raw_parts(start.as_ptr(), length) })
+}
+
+// The SMBIOS table the firmware left in memory, found by scanning the BIOS
+// area; 64-bit entry points are preferred. Needs `memory::init`. UEFI
+// systems publish the entry point in the system table instead, and the
+// bootloader does not pass that on.
+pub fn table() -> Option<Table<'static>> {
+    let area = physical_slice(SCAN_START, (SCAN_END - SCAN_START) as usize)?;
+    let entry_points = || (0..area.len()).step_by(16).map(|offset| &area[offset..]);
+    let (address, length) = entry_points()
+        .filter(|bytes| bytes.starts_with(b"_SM3_"))
+        .chain(entry_points().filter(|bytes| bytes.starts_with(b"_SM_")))
+        .find_map(parse_entry_point)?;
+    physical_slice(address, length).map(Table::new)
+}
+
+pub fn system_info() -> Option<SystemInfo<'static>> {
+    table().map(|table| table.system_info())
+}
+
+#[test_case]
+fn test_walk_structures_and_strings() {
+    const TABLE: &[u8] = &[
+        // BIOS information, two strings.
+        0, 6, 0, 0, 1, 2, b'S', b'e', b'a', b'B', b'I', b'O', b'S', 0, b'1', b'.', b'1', b'6', 0, 0,
+        // System information.
+        1, 6, 1, 0, 1, 2, b'Q', b'E', b'M', b'U', 0, b'P', b'C', b' ', 0, 0,
+        // A 1 GiB memory device, then one of unknown size, neither with strings.
+        17, 0x11, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x00, 0x04, 0, 0, 0, 0, 0,
+        17, 0x11, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 0, 0, 0,
+        // End of table; anything after it is ignored.
+        127, 4, 4, 0, 0, 0,
+        1, 6, 5, 0, 1, 0, b'X', 0, 0,
+    ];
+    let table = Table::new(TABLE);
+    assert_eq!(table.structures().count(), 5);
+    assert!(table.memory_devices().map(|device| device.size_mib).eq([Some(1024), None]));
+    assert_eq!(table.system_info(), SystemInfo {
+        bios_vendor: Some("SeaBIOS"),
+        bios_version: Some("1.16"),
+        manufacturer: Some("QEMU"),
+        product: Some("PC"),
+        memory_devices: 2,
+        memory_mib: 1024,
+    });
+}
+
+#[test_case]
+fn test_qemu_reports_its_manufacturer() {
+    if crate::platform::is_qemu() {
+        let info = system_info().expect("no SMBIOS table");
+        assert_eq!(info.manufacturer, Some("QEMU"));
+    }
+}
@@ -0,0 +1,148 @@
+use core::ops::{Deref, DerefMut};
+use spin::{Mutex, MutexGuard};
+
+// A mutex with a declared place in the global lock order. Locks must be
+// taken in increasing level; in debug builds taking a lock while holding one
+// of the same or a higher level panics, which catches ordering cycles before
+// they can deadlock. Release builds only keep the mutex.
+pub struct OrderedMutex<T> {
+    #[cfg(debug_assertions)]
+    name: &'static str,
+    #[cfg(debug_assertions)]
+    level: u8,
+    inner: Mutex<T>,
+}
+
+pub struct OrderedMutexGuard<'a, T> {
+    // `None` for a guard from `try_lock`, which is not tracked.
+    #[cfg(debug_assertions)]
+    level: Option<u8>,
+    guard: MutexGuard<'a, T>,
+}
+
+// The kernel's lock order, outermost first. A lock may only be taken while
+// holding locks listed above it. Interrupt handlers take the locks at the
+// bottom, so everything above must be held with interrupts disabled or
+// never be taken from a handler.
+pub mod level {
+    // `vga_buffer::WRITER`, held while the terminal parses escapes.
+    pub const VGA_WRITER: u8 = 1;
+    pub const TERMINAL: u8 = 2;
+    // The decoder, held while IRQ 1 updates the held keys and the queue.
+    pub const KEYBOARD: u8 = 3;
+    pub const PRESSED_KEYS: u8 = 4;
+    pub const KEY_QUEUE: u8 = 5;
+    pub const SERIAL_TX: u8 = 6;
+    // Taken last by every IRQ handler, for the EOI.
+    pub const PICS: u8 = 7;
+}
+
+impl<T> OrderedMutex<T> {
+    #[allow(unused_variables)]
+    pub const fn new(name: &'static str, level: u8, value: T) -> Self {
+        assert!((level as u32) < u64::BITS);
+        OrderedMutex {
+            #[cfg(debug_assertions)]
+            name,
+            #[cfg(debug_assertions)]
+            level,
+            inner: Mutex::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> OrderedMutexGuard<'_, T> {
+        #[cfg(debug_assertions)]
+        lock_order::acquire(self.name, self.level);
+        OrderedMutexGuard {
+            #[cfg(debug_assertions)]
+            level: Some(self.level),
+            guard: self.inner.lock(),
+        }
+    }
+
+    // Never spins, so it cannot deadlock whatever is held and skips the
+    // order check; for paths like panic output that may have interrupted a
+    // holder.
+    pub fn try_lock(&self) -> Option<OrderedMutexGuard<'_, T>> {
+        Some(OrderedMutexGuard {
+            #[cfg(debug_assertions)]
+            level: None,
+            guard: self.inner.try_lock()?,
+        })
+    }
+}
+
+impl<T> Deref for OrderedMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for OrderedMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+#[cfg(debug_assertions)]
+impl<T> Drop for OrderedMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        if let Some(level) = self.level {
+            lock_order::release(level);
+        }
+    }
+}
+
+// There is a single CPU, so one mask of held levels covers every context;
+// interrupt handlers nest on top of whatever they interrupted.
+#[cfg(debug_assertions)]
+mod lock_order {
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    static HELD: AtomicU64 = AtomicU64::new(0);
+
+    pub fn acquire(name: &str, level: u8) {
+        let held = HELD.load(Ordering::Relaxed);
+        if held >> level != 0 {
+            HELD.store(0, Ordering::Relaxed);
+            panic!("lock order violation: taking {} (level {}) while holding levels {:#b}",
+                name, level, held);
+        }
+        HELD.store(held | 1 << level, Ordering::Relaxed);
+    }
+
+    pub fn release(level: u8) {
+        HELD.fetch_and(!(1 << level), Ordering::Relaxed);
+    }
+}
+
+#[test_case]
+fn test_increasing_order_is_allowed() {
+    static OUTER: OrderedMutex<u32> = OrderedMutex::new("outer", 1, 0);
+    static INNER: OrderedMutex<u32> = OrderedMutex::new("inner", 2, 0);
+
+    for _ in 0..2 {
+        let mut outer = OUTER.lock();
+        let mut inner = INNER.lock();
+        *outer += 1;
+        *inner += *outer;
+    }
+    assert_eq!(*INNER.lock(), 3);
+}
+
+#[test_case]
+fn test_try_lock_skips_order_check() {
+    static LOW: OrderedMutex<()> = OrderedMutex::new("low", 1, ());
+    static HIGH: OrderedMutex<()> = OrderedMutex::new("high", 2, ());
+
+    {
+        let _high = HIGH.lock();
+        assert!(LOW.try_lock().is_some());
+        assert!(HIGH.try_lock().is_none());
+    }
+    // The untracked guard released nothing, so the order is intact.
+    let _low = LOW.lock();
+    let _high = HIGH.lock();
+}
@@ -0,0 +1,258 @@
+use core::fmt;
+use crate::sync::{level, OrderedMutex};
+use crate::error::{KernelError, KernelResult};
+use crate::vga_buffer::{Color, Writer, BUFFER_WIDTH};
+
+const ESC: u8 = 0x1b;
+const MAX_PARAMS: usize = 4;
+
+const DEFAULT_FOREGROUND: Color = Color::Yellow;
+const DEFAULT_BACKGROUND: Color = Color::Black;
+
+// ANSI color numbers 0-7 in VGA order; the bright variants use the same
+// index into the second half.
+const ANSI_COLORS: [Color; 16] = [
+    Color::Black, Color::Red, Color::Green, Color::Brown,
+    Color::Blue, Color::Magenta, Color::Cyan, Color::LightGray,
+    Color::DarkGray, Color::LightRed, Color::LightGreen, Color::Yellow,
+    Color::LightBlue, Color::Pink, Color::LightCyan, Color::White,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+}
+
+// Interprets the subset of VT100/ANSI escape sequences the console needs:
+// cursor positioning and movement, erase, SGR colors and save/restore. The
+// parser state survives between writes, so a sequence may be split across
+// `print!` calls; unknown sequences are consumed up to their final byte.
+pub struct Terminal {
+    state: State,
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    foreground: Color,
+    background: Color,
+    saved: (usize, usize),
+}
+
+impl Terminal {
+    pub const fn new() -> Self {
+        Terminal {
+            state: State::Ground,
+            params: [0; MAX_PARAMS],
+            len: 0,
+            foreground: DEFAULT_FOREGROUND,
+            background: DEFAULT_BACKGROUND,
+            saved: (0, 0),
+        }
+    }
+
+    pub fn write_str(&mut self, writer: &mut Writer, s: &str) {
+        let bytes = s.as_bytes();
+        let mut text_start = 0;
+        for (i, &byte) in bytes.iter().enumerate() {
+            // Sequences are ASCII. Any other byte starts a character, which
+            // cuts the sequence short and is printed as text; `text_start`
+            // already points at it.
+            if self.state != State::Ground && !byte.is_ascii() {
+                self.state = State::Ground;
+            }
+            if self.state == State::Ground {
+                if byte != ESC {
+                    continue;
+                }
+                // ESC is ASCII, so the run before one is valid UTF-8.
+                writer.write_string(&s[text_start..i]);
+            }
+            self.advance(writer, byte);
+            text_start = i + 1;
+        }
+        if self.state == State::Ground {
+            writer.write_string(&s[text_start..]);
+        }
+    }
+
+    fn advance(&mut self, writer: &mut Writer, byte: u8) {
+        match (self.state, byte) {
+            (State::Ground, _) => {
+                self.state = State::Escape;
+            }
+            (State::Escape, b'[') => {
+                self.params = [0; MAX_PARAMS];
+                self.len = 0;
+                self.state = State::Csi;
+            }
+            (State::Escape, b'7') => {
+                self.saved = writer.position();
+                self.state = State::Ground;
+            }
+            (State::Escape, b'8') => {
+                self.move_to(writer, self.saved.0, self.saved.1);
+                self.state = State::Ground;
+            }
+            (State::Escape, _) => self.state = State::Ground,
+            (State::Csi, b'0'..=b'9') => {
+                let param = &mut self.params[self.len.min(MAX_PARAMS - 1)];
+                *param = param.saturating_mul(10).saturating_add(u16::from(byte - b'0'));
+                self.len = self.len.max(1);
+            }
+            (State::Csi, b';') => {
+                self.len = (self.len.max(1) + 1).min(MAX_PARAMS);
+            }
+            (State::Csi, 0x40..=0x7e) => {
+                self.execute(writer, byte);
+                self.state = State::Ground;
+            }
+            // Intermediate and private-marker bytes (e.g. `?`) are ignored.
+            (State::Csi, _) => {}
+        }
+    }
+
+    fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params[index] {
+            0 => default,
+            value => value,
+        }
+    }
+
+    fn execute(&mut self, writer: &mut Writer, final_byte: u8) {
+        let (row, col) = writer.position();
+        let count = usize::from(self.param(0, 1));
+        match final_byte {
+            b'H' | b'f' => {
+                let row = usize::from(self.param(0, 1)) - 1;
+                let col = usize::from(self.param(1, 1)) - 1;
+                self.move_to(writer, row, col);
+            }
+            b'A' => self.move_to(writer, row.saturating_sub(count), col),
+            b'B' => self.move_to(writer, row + count, col),
+            b'C' => self.move_to(writer, row, col + count),
+            b'D' => self.move_to(writer, row, col.saturating_sub(count)),
+            b'J' if self.params[0] == 2 => {
+                writer.clear_screen();
+                self.move_to(writer, row, col);
+            }
+            b'K' if self.params[0] == 0 => writer.clear_to_end_of_line(),
+            b'm' => self.select_graphic_rendition(writer),
+            b's' => self.saved = (row, col),
+            b'u' => self.move_to(writer, self.saved.0, self.saved.1),
+            _ => {}
+        }
+    }
+
+    fn move_to(&self, writer: &mut Writer, row: usize, col: usize) {
+        let row = row.min(writer.text_height() - 1);
+        let col = col.min(BUFFER_WIDTH - 1);
+        writer.set_position(row, col).expect("clamped position is on screen");
+    }
+
+    fn select_graphic_rendition(&mut self, writer: &mut Writer) {
+        for &param in &self.params[..self.len.max(1)] {
+            match param {
+                0 => {
+                    self.foreground = DEFAULT_FOREGROUND;
+                    self.background = DEFAULT_BACKGROUND;
+                }
+                30..=37 => self.foreground = ANSI_COLORS[usize::from(param - 30)],
+                39 => self.foreground = DEFAULT_FOREGROUND,
+                40..=47 => self.background = ANSI_COLORS[usize::from(param - 40)],
+                49 => self.background = DEFAULT_BACKGROUND,
+                90..=97 => self.foreground = ANSI_COLORS[usize::from(param - 90) + 8],
+                100..=107 => self.background = ANSI_COLORS[usize::from(param - 100) + 8],
+                _ => {}
+            }
+        }
+        writer.set_color(self.foreground, self.background);
+    }
+}
+
+impl Default for Terminal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static TERMINAL: OrderedMutex<Terminal> =
+    OrderedMutex::new("terminal", level::TERMINAL, Terminal::new());
+
+struct Output<'a> {
+    terminal: &'a mut Terminal,
+    writer: &'a mut Writer,
+}
+
+impl fmt::Write for Output<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.terminal.write_str(self.writer, s);
+        Ok(())
+    }
+}
+
+// The caller holds the writer lock, which is always taken before the
+// terminal lock.
+pub fn write_fmt(writer: &mut Writer, args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    let mut terminal = TERMINAL.lock();
+    let mut output = Output { terminal: &mut terminal, writer };
+    output.write_fmt(args).unwrap();
+}
+
+pub fn try_write_fmt(writer: &mut Writer, args: fmt::Arguments) -> KernelResult<()> {
+    use core::fmt::Write;
+
+    let mut terminal = TERMINAL.try_lock().ok_or(KernelError::WouldBlock)?;
+    let mut output = Output { terminal: &mut terminal, writer };
+    output.write_fmt(args).unwrap();
+    Ok(())
+}
+
+#[test_case]
+fn test_clear_and_cursor_position() {
+    use x86_64::instructions::interrupts;
+    use crate::vga_buffer::{ColorCode, WRITER};
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let mut terminal = Terminal::new();
+        writer.write_string("left behind");
+        terminal.write_str(&mut writer, "\x1b[2J\x1b[5;10Hhi\x1b[31;44m!\x1b[0m\x1b[?25l");
+        terminal.write_str(&mut writer, "\x1b[");
+        terminal.write_str(&mut writer, "3D?");
+
+        // Rows and columns are 1-based in VT100, so 5;10 is (4, 9).
+        let plain = ColorCode::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND);
+        assert_eq!(writer.read_at(4, 9), Ok((b'?', plain)));
+        assert_eq!(writer.read_at(4, 10), Ok((b'i', plain)));
+        assert_eq!(writer.read_at(4, 11), Ok((b'!', ColorCode::new(Color::Red, Color::Blue))));
+        assert_eq!(writer.position(), (4, 10));
+        for row in 0..writer.text_height() {
+            for col in 0..BUFFER_WIDTH {
+                if row != 4 || !(9..12).contains(&col) {
+                    assert_eq!(writer.read_at(row, col).map(|cell| cell.0), Ok(b' '));
+                }
+            }
+        }
+    });
+}
+
+#[test_case]
+fn test_non_ascii_byte_ends_sequence() {
+    use x86_64::instructions::interrupts;
+    use crate::vga_buffer::WRITER;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let mut terminal = Terminal::new();
+        terminal.write_str(&mut writer, "\x1b[2;1H\x1bé\x1b[é");
+        terminal.write_str(&mut writer, "\x1b[");
+        terminal.write_str(&mut writer, "éx");
+
+        // Each é is two bytes, and the writer shows each as 0xfe.
+        let cells = (0..7).map(|col| writer.read_at(1, col).unwrap().0);
+        assert!(cells.eq([0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0xfe, b'x']));
+        assert_eq!(terminal.state, State::Ground);
+    });
+}
@@ -1,7 +1,9 @@
 use volatile::Volatile;
 use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
 use lazy_static::lazy_static;
-use spin::Mutex;
+use crate::sync::{level, OrderedMutex};
+use crate::error::{KernelError, KernelResult};
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -27,10 +29,10 @@ pub enum Color {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
-struct ColorCode(u8);
+pub struct ColorCode(u8);
 
 impl ColorCode {
-    fn new(foreground: Color, background: Color) -> ColorCode {
+    pub fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
 }
@@ -42,30 +44,109 @@ struct ScreenChar {
     color_code: ColorCode,
 }
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+pub const BUFFER_HEIGHT: usize = 25;
+pub const BUFFER_WIDTH: usize = 80;
+pub const STATUS_ROW: usize = BUFFER_HEIGHT - 1;
+
+// Mouse counts per text cell.
+const POINTER_X_SCALE: i32 = 8;
+const POINTER_Y_SCALE: i32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfBounds {
+    pub row: usize,
+    pub col: usize,
+}
+
+impl From<OutOfBounds> for KernelError {
+    fn from(_: OutOfBounds) -> Self {
+        KernelError::InvalidArgument
+    }
+}
 
 #[repr(transparent)]
 struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+impl Buffer {
+    fn row_ptr(&mut self, row: usize) -> *mut u16 {
+        self.chars[row].as_mut_ptr().cast()
+    }
+
+    // Row copies and fills use string instructions on whole cells; the asm
+    // blocks are opaque to the compiler, so the MMIO writes are never elided.
+    fn copy_rows(&mut self, src_row: usize, dst_row: usize, rows: usize) {
+        assert!(src_row + rows <= BUFFER_HEIGHT && dst_row + rows <= BUFFER_HEIGHT);
+        assert!(dst_row <= src_row);
+        let src = self.row_ptr(src_row);
+        let dst = self.row_ptr(dst_row);
+        unsafe {
+            core::arch::asm!(
+                "rep movsw",
+                inout("rsi") src => _,
+                inout("rdi") dst => _,
+                inout("rcx") rows * BUFFER_WIDTH => _,
+                options(nostack, preserves_flags),
+            );
+        }
+    }
+
+    fn fill_rows(&mut self, row: usize, rows: usize, blank: ScreenChar) {
+        assert!(row + rows <= BUFFER_HEIGHT);
+        let cell = u16::from(blank.color_code.0) << 8 | u16::from(blank.ascii_character);
+        let dst = self.row_ptr(row);
+        unsafe {
+            core::arch::asm!(
+                "rep stosw",
+                inout("rdi") dst => _,
+                inout("rcx") rows * BUFFER_WIDTH => _,
+                in("ax") cell,
+                options(nostack, preserves_flags),
+            );
+        }
+    }
+}
+
+// The pointer is drawn by inverting the attribute of the cell under it. The
+// original cell is kept aside and put back whenever anything else writes to
+// the screen, so text output never sees (or overwrites) the pointer.
+struct Pointer {
+    x: i32,
+    y: i32,
+    saved: ScreenChar,
+}
+
+impl Pointer {
+    fn cell(&self) -> (usize, usize) {
+        ((self.y / POINTER_Y_SCALE) as usize, (self.x / POINTER_X_SCALE) as usize)
+    }
+}
+
 pub struct Writer {
+    text_height: usize,
+    row_position: usize,
     column_position: usize,
     color_code: ColorCode,
+    pointer: Option<Pointer>,
     buffer: &'static mut Buffer,
 }
 
 impl Writer {
     pub fn write_byte(&mut self, byte: u8) {
+        self.with_pointer_hidden(|writer| writer.put_byte(byte));
+    }
+
+    fn put_byte(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
+            0x08 => self.backspace(),
             byte => {
                 if self.column_position >= BUFFER_WIDTH {
                     self.new_line();
                 }
 
-                let row = BUFFER_HEIGHT - 1;
+                let row = self.row_position;
                 let col = self.column_position;
 
                 let color_code = self.color_code;
@@ -79,44 +160,193 @@ impl Writer {
     }
 
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                _ => self.write_byte(0xfe),
+        self.with_pointer_hidden(|writer| {
+            for byte in s.bytes() {
+                match byte {
+                    0x20..=0x7e | b'\n' | 0x08 => writer.put_byte(byte),
+                    _ => writer.put_byte(0xfe),
+                }
             }
+        });
+    }
+
+    pub fn set_position(&mut self, row: usize, col: usize) -> Result<(), OutOfBounds> {
+        check_bounds(row, col)?;
+        if row >= self.text_height {
+            return Err(OutOfBounds { row, col });
         }
+        self.row_position = row;
+        self.column_position = col;
+        Ok(())
     }
 
-    fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
+    pub fn position(&self) -> (usize, usize) {
+        (self.row_position, self.column_position)
+    }
+
+    pub fn text_height(&self) -> usize {
+        self.text_height
+    }
+
+    pub fn read_at(&self, row: usize, col: usize) -> Result<(u8, ColorCode), OutOfBounds> {
+        check_bounds(row, col)?;
+        let (pointer_row, pointer_col) = self.pointer_position().unwrap_or((BUFFER_HEIGHT, 0));
+        let screen_char = match &self.pointer {
+            Some(pointer) if (row, col) == (pointer_row, pointer_col) => pointer.saved,
+            _ => self.buffer.chars[row][col].read(),
+        };
+        Ok((screen_char.ascii_character, screen_char.color_code))
+    }
+
+    pub fn write_at(&mut self, row: usize, col: usize, byte: u8, color_code: ColorCode)
+        -> Result<(), OutOfBounds>
+    {
+        check_bounds(row, col)?;
+        self.with_pointer_hidden(|writer| {
+            writer.buffer.chars[row][col].write(ScreenChar {
+                ascii_character: byte,
+                color_code,
+            });
+        });
+        Ok(())
+    }
+
+    // Moves the pointer by a mouse delta, showing it on first use.
+    pub fn move_pointer(&mut self, dx: i16, dy: i16) {
+        let (x, y) = match self.hide_pointer() {
+            Some(pointer) => (pointer.x, pointer.y),
+            None => (BUFFER_WIDTH as i32 / 2 * POINTER_X_SCALE,
+                BUFFER_HEIGHT as i32 / 2 * POINTER_Y_SCALE),
+        };
+        let x = (x + i32::from(dx)).clamp(0, BUFFER_WIDTH as i32 * POINTER_X_SCALE - 1);
+        let y = (y + i32::from(dy)).clamp(0, BUFFER_HEIGHT as i32 * POINTER_Y_SCALE - 1);
+        self.show_pointer(x, y);
+    }
+
+    pub fn pointer_position(&self) -> Option<(usize, usize)> {
+        self.pointer.as_ref().map(Pointer::cell)
+    }
+
+    fn hide_pointer(&mut self) -> Option<Pointer> {
+        let pointer = self.pointer.take()?;
+        let (row, col) = pointer.cell();
+        self.buffer.chars[row][col].write(pointer.saved);
+        Some(pointer)
+    }
+
+    fn show_pointer(&mut self, x: i32, y: i32) {
+        let (row, col) = ((y / POINTER_Y_SCALE) as usize, (x / POINTER_X_SCALE) as usize);
+        let saved = self.buffer.chars[row][col].read();
+        let attribute = saved.color_code.0;
+        self.buffer.chars[row][col].write(ScreenChar {
+            ascii_character: saved.ascii_character,
+            color_code: ColorCode(attribute.rotate_left(4)),
+        });
+        self.pointer = Some(Pointer { x, y, saved });
+    }
+
+    fn with_pointer_hidden<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let pointer = self.hide_pointer();
+        let result = f(self);
+        if let Some(pointer) = pointer {
+            self.show_pointer(pointer.x, pointer.y);
+        }
+        result
+    }
+
+    pub fn set_status(&mut self, text: &str) {
+        self.with_pointer_hidden(|writer| writer.draw_status(text));
+    }
+
+    fn draw_status(&mut self, text: &str) {
+        if self.text_height == BUFFER_HEIGHT {
+            if self.row_position == STATUS_ROW {
+                self.scroll();
+                self.row_position -= 1;
             }
+            self.text_height = STATUS_ROW;
+        }
+
+        let color_code = ColorCode::new(Color::Black, Color::LightGray);
+        let mut bytes = text.bytes();
+        for col in 0..BUFFER_WIDTH {
+            let ascii_character = match bytes.next() {
+                Some(byte @ 0x20..=0x7e) => byte,
+                Some(_) => 0xfe,
+                None => b' ',
+            };
+            self.buffer.chars[STATUS_ROW][col].write(ScreenChar {
+                ascii_character,
+                color_code,
+            });
         }
-        self.clear_row(BUFFER_HEIGHT - 1);
+    }
+
+    fn backspace(&mut self) {
+        if self.column_position > 0 {
+            self.column_position -= 1;
+            let (row, col) = (self.row_position, self.column_position);
+            let color_code = self.color_code;
+            self.buffer.chars[row][col].write(ScreenChar {
+                ascii_character: b' ',
+                color_code,
+            });
+        }
+    }
+
+    fn new_line(&mut self) {
         self.column_position = 0;
+        if self.row_position < self.text_height - 1 {
+            self.row_position += 1;
+            return;
+        }
+        self.scroll();
+    }
+
+    fn scroll(&mut self) {
+        self.buffer.copy_rows(1, 0, self.text_height - 1);
+        self.clear_rows(self.text_height - 1, 1);
     }
 
-    fn clear_row(&mut self, row: usize) {
+    fn clear_rows(&mut self, row: usize, rows: usize) {
         let blank = ScreenChar {
             ascii_character: b' ',
             color_code: self.color_code,
         };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
-        }
+        self.buffer.fill_rows(row, rows, blank);
     }
 
     pub fn set_color(&mut self, foreground: Color, background: Color) {
         self.color_code = ColorCode::new(foreground, background);
     }
 
+    pub fn clear_to_end_of_line(&mut self) {
+        let row = self.row_position;
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        self.with_pointer_hidden(|writer| {
+            for col in writer.column_position..BUFFER_WIDTH {
+                writer.buffer.chars[row][col].write(blank);
+            }
+        });
+    }
+
     pub fn clear_screen(&mut self) {
-        for row in 0..BUFFER_HEIGHT {
-            self.clear_row(row);
-        }
-        self.column_position = 0;
+        self.with_pointer_hidden(|writer| {
+            writer.clear_rows(0, writer.text_height);
+            writer.row_position = writer.text_height - 1;
+            writer.column_position = 0;
+        });
+    }
+}
+
+fn check_bounds(row: usize, col: usize) -> Result<(), OutOfBounds> {
+    if row < BUFFER_HEIGHT && col < BUFFER_WIDTH {
+        Ok(())
+    } else {
+        Err(OutOfBounds { row, col })
     }
 }
 
@@ -128,20 +358,89 @@ impl fmt::Write for Writer {
 }
 
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::Yellow, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    });
+    pub static ref WRITER: OrderedMutex<Writer> =
+        OrderedMutex::new("vga writer", level::VGA_WRITER, Writer {
+            text_height: BUFFER_HEIGHT,
+            row_position: BUFFER_HEIGHT - 1,
+            column_position: 0,
+            color_code: ColorCode::new(Color::Yellow, Color::Black),
+            pointer: None,
+            buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+        });
+}
+
+// Where `print!` and `println!` go, chosen with `console=`. Serial suits
+// headless runs; the status row and the mouse pointer stay on the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    Vga,
+    Serial,
+}
+
+impl Console {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "vga" => Some(Console::Vga),
+            "serial" => Some(Console::Serial),
+            _ => None,
+        }
+    }
+}
+
+static SERIAL_CONSOLE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_console(console: Console) {
+    SERIAL_CONSOLE.store(console == Console::Serial, Ordering::Relaxed);
+}
+
+pub fn console() -> Console {
+    if SERIAL_CONSOLE.load(Ordering::Relaxed) {
+        Console::Serial
+    } else {
+        Console::Vga
+    }
 }
 
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    use core::fmt::Write;
     use x86_64::instructions::interrupts;
-    
+
+    if console() == Console::Serial {
+        return crate::serial::_print(args);
+    }
+    interrupts::without_interrupts(|| {
+        crate::terminal::write_fmt(&mut WRITER.lock(), args);
+    });
+}
+
+// Never spins: if the writer is busy (e.g. in an interrupt or panic handler
+// that interrupted a print), the output is dropped and `WouldBlock` returned.
+#[doc(hidden)]
+pub fn _try_print(args: fmt::Arguments) -> KernelResult<()> {
+    use x86_64::instructions::interrupts;
+
+    if console() == Console::Serial {
+        return crate::serial::_try_print(args);
+    }
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.try_lock().ok_or(KernelError::WouldBlock)?;
+        crate::terminal::try_write_fmt(&mut writer, args)
+    })
+}
+
+pub fn set_status(text: &str) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        WRITER.lock().set_status(text);
+    });
+}
+
+pub fn move_pointer(dx: i16, dy: i16) {
+    use x86_64::instructions::interrupts;
+
     interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
+        WRITER.lock().move_pointer(dx, dy);
     });
 }
 
@@ -156,3 +455,161 @@ macro_rules! println {
     ($fmt:expr) => ($crate::vga_buffer::_print(format_args!(concat!($fmt, "\n"))));
     ($fmt:expr, $($arg:tt)*) => ($crate::vga_buffer::_print(format_args!(concat!($fmt, "\n"), $($arg)*)));
 }
+
+#[macro_export]
+macro_rules! try_print {
+    ($($arg:tt)*) => ($crate::vga_buffer::_try_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! try_println {
+    () => ($crate::vga_buffer::_try_print(format_args!("\n")));
+    ($fmt:expr) => ($crate::vga_buffer::_try_print(format_args!(concat!($fmt, "\n"))));
+    ($fmt:expr, $($arg:tt)*) => ($crate::vga_buffer::_try_print(format_args!(concat!($fmt, "\n"), $($arg)*)));
+}
+
+#[test_case]
+fn test_write_at_buffer_offset() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let color_code = ColorCode::new(Color::White, Color::Blue);
+        writer.write_at(10, 40, b'X', color_code).unwrap();
+        assert_eq!(writer.write_at(25, 0, b'X', color_code), Err(OutOfBounds { row: 25, col: 0 }));
+        assert!(writer.set_position(0, 80).is_err());
+
+        let cell = (0xb8000 + 2 * (10 * BUFFER_WIDTH + 40)) as *const u8;
+        let (byte, attribute) = unsafe {
+            (core::ptr::read_volatile(cell), core::ptr::read_volatile(cell.add(1)))
+        };
+        assert_eq!(byte, b'X');
+        assert_eq!(attribute, color_code.0);
+    });
+}
+
+#[test_case]
+fn test_serial_console_leaves_screen_alone() {
+    use x86_64::instructions::interrupts;
+
+    assert_eq!(Console::from_name("serial"), Some(Console::Serial));
+    assert_eq!(Console::from_name("tty0"), None);
+
+    let position = || interrupts::without_interrupts(|| WRITER.lock().position());
+    let before = position();
+    set_console(Console::Serial);
+    println!("printed to the serial console");
+    let after = position();
+    set_console(Console::Vga);
+    assert_eq!(after, before);
+}
+
+#[test_case]
+fn test_status_row_survives_scrolling() {
+    use x86_64::instructions::interrupts;
+
+    let status = "MentOS status";
+    set_status(status);
+    for _ in 0..2 * BUFFER_HEIGHT {
+        println!("scrolling output that must not reach the status row");
+    }
+
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        for (i, c) in status.bytes().enumerate() {
+            let screen_char = writer.buffer.chars[STATUS_ROW][i].read();
+            assert_eq!(char::from(screen_char.ascii_character), char::from(c));
+        }
+        assert_eq!(writer.buffer.chars[STATUS_ROW][status.len()].read().ascii_character, b' ');
+    });
+}
+
+#[test_case]
+fn test_clear_and_scroll_preserve_cells() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let color_code = ColorCode::new(Color::LightGreen, Color::Blue);
+        writer.color_code = color_code;
+        writer.clear_screen();
+        let blank = ScreenChar { ascii_character: b' ', color_code };
+        for row in 0..writer.text_height {
+            for col in 0..BUFFER_WIDTH {
+                assert_eq!(writer.buffer.chars[row][col].read(), blank);
+            }
+        }
+
+        writer.write_at(1, 79, b'A', color_code).unwrap();
+        writer.scroll();
+        assert_eq!(writer.buffer.chars[0][79].read().ascii_character, b'A');
+        assert_eq!(writer.buffer.chars[writer.text_height - 1][0].read(), blank);
+        writer.set_color(Color::Yellow, Color::Black);
+    });
+}
+
+#[test_case]
+fn test_pointer_restores_cell_under_it() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let color_code = ColorCode::new(Color::White, Color::Blue);
+        let inverted = ColorCode::new(Color::Blue, Color::White);
+        writer.write_at(12, 40, b'P', color_code).unwrap();
+        writer.write_at(12, 43, b'Q', color_code).unwrap();
+
+        writer.move_pointer(0, 0);
+        assert_eq!(writer.pointer_position(), Some((12, 40)));
+        assert_eq!(writer.buffer.chars[12][40].read(),
+            ScreenChar { ascii_character: b'P', color_code: inverted });
+
+        writer.move_pointer(3 * POINTER_X_SCALE as i16, 0);
+        assert_eq!(writer.pointer_position(), Some((12, 43)));
+        assert_eq!(writer.buffer.chars[12][40].read(),
+            ScreenChar { ascii_character: b'P', color_code });
+        assert_eq!(writer.buffer.chars[12][43].read(),
+            ScreenChar { ascii_character: b'Q', color_code: inverted });
+
+        // Writing under the pointer keeps it on top of the new character.
+        writer.write_at(12, 43, b'R', color_code).unwrap();
+        assert_eq!(writer.buffer.chars[12][43].read().ascii_character, b'R');
+        writer.move_pointer(i16::MIN, i16::MIN);
+        assert_eq!(writer.pointer_position(), Some((0, 0)));
+        assert_eq!(writer.buffer.chars[12][43].read(),
+            ScreenChar { ascii_character: b'R', color_code });
+
+        writer.hide_pointer();
+    });
+}
+
+#[cfg(feature = "bench")]
+#[test_case]
+fn bench_scroll() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let text_height = writer.text_height;
+        crate::bench!("vga scroll (scalar)", 1000, || {
+            for row in 1..text_height {
+                for col in 0..BUFFER_WIDTH {
+                    let character = writer.buffer.chars[row][col].read();
+                    writer.buffer.chars[row - 1][col].write(character);
+                }
+            }
+        });
+        crate::bench!("vga scroll (rep movsw)", 1000, || writer.scroll());
+    });
+}
+
+#[test_case]
+fn test_try_println_with_writer_locked() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let _writer = WRITER.lock();
+        assert_eq!(try_println!("dropped while the writer is held"), Err(KernelError::WouldBlock));
+    });
+    assert_eq!(try_println!("printed once the writer is free"), Ok(()));
+}
@@ -0,0 +1,122 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::panic::PanicInfo;
+use bootloader::{entry_point, BootInfo};
+use lazy_static::lazy_static;
+use ment_os::interrupts::{Exception, IdtBuilder};
+use ment_os::memory::{self, BitmapFrameAllocator};
+use ment_os::{exit_qemu, exit_token, serial_print, serial_println, QemuExitCode};
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags, PhysFrame};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+// #AC is only raised at CPL 3, so the misaligned load runs from a user page.
+const USER_CODE: u64 = 0x7000_0000_0000;
+// mov eax, [rip + 1]: a dword load from USER_CODE + 7. Then jmp $, should
+// the load not fault.
+const MISALIGNED_LOAD: [u8; 8] = [0x8b, 0x05, 0x01, 0x00, 0x00, 0x00, 0xeb, 0xfe];
+
+const AC_IST_INDEX: u16 = 0;
+
+lazy_static! {
+    // The fault arrives from ring 3, so the handler needs a stack of its own.
+    static ref TSS: TaskStateSegment = {
+        const STACK_SIZE: usize = 4096 * 5;
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+        let mut tss = TaskStateSegment::new();
+        tss.interrupt_stack_table[AC_IST_INDEX as usize] =
+            VirtAddr::from_ptr(core::ptr::addr_of!(STACK)) + STACK_SIZE;
+        tss
+    };
+
+    static ref GDT: (GlobalDescriptorTable, [SegmentSelector; 4]) = {
+        let mut gdt = GlobalDescriptorTable::new();
+        let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let user_data = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code = gdt.add_entry(Descriptor::user_code_segment());
+        let tss = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        (gdt, [kernel_code, user_data, user_code, tss])
+    };
+
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = IdtBuilder::new();
+        unsafe {
+            idt.set_exception(Exception::ALIGNMENT_CHECK, test_alignment_check_handler)
+                .set_stack_index(AC_IST_INDEX);
+        }
+        idt.build()
+    };
+}
+
+extern "x86-interrupt" fn test_alignment_check_handler(
+    stack_frame: InterruptStackFrame, error_code: u64)
+{
+    let from_user = stack_frame.code_segment & 3 == 3;
+    if from_user && stack_frame.instruction_pointer.as_u64() == USER_CODE && error_code == 0 {
+        serial_println!("[ok]");
+        exit_qemu(exit_token(), QemuExitCode::Success);
+    } else {
+        serial_println!("[unexpected #AC]\n{:#?}", stack_frame);
+        exit_qemu(exit_token(), QemuExitCode::Failed);
+    }
+    ment_os::hlt_loop();
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use x86_64::instructions::segmentation::{Segment, CS};
+    use x86_64::instructions::tables::load_tss;
+    use x86_64::registers::control::{Cr0, Cr0Flags};
+    use x86_64::registers::rflags::RFlags;
+
+    serial_print!("alignment_check::misaligned_user_load_raises_ac...\t");
+
+    let mut mapper = unsafe { memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) }
+        .expect("no usable memory");
+    let page: Page = Page::containing_address(VirtAddr::new(USER_CODE));
+    let frame: PhysFrame = frame_allocator.allocate_zeroed_frame().expect("out of memory");
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    unsafe { mapper.map_to_with_table_flags(page, frame, flags, flags, &mut frame_allocator) }
+        .expect("mapping the user page failed")
+        .flush();
+    unsafe {
+        core::ptr::copy_nonoverlapping(MISALIGNED_LOAD.as_ptr(), USER_CODE as *mut u8,
+            MISALIGNED_LOAD.len());
+    }
+
+    let [kernel_code, user_data, user_code, tss] = GDT.1;
+    GDT.0.load();
+    unsafe {
+        CS::set_reg(kernel_code);
+        load_tss(tss);
+    }
+    TEST_IDT.load();
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::ALIGNMENT_MASK)) };
+
+    // Enter ring 3 at the load with EFLAGS.AC set and interrupts off. The
+    // user stack is never touched, so the top of the code page will do.
+    let rflags = RFlags::ALIGNMENT_CHECK.bits() | 1 << 1;
+    unsafe {
+        core::arch::asm!(
+            "push {ss}", "push {rsp}", "push {rflags}", "push {cs}", "push {rip}", "iretq",
+            ss = in(reg) u64::from(user_data.0),
+            rsp = in(reg) USER_CODE + 4096,
+            rflags = in(reg) rflags,
+            cs = in(reg) u64::from(user_code.0),
+            rip = in(reg) USER_CODE,
+            options(noreturn),
+        );
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ment_os::test_panic_handler(info)
+}
@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use ment_os::interrupts::{Exception, IdtBuilder};
+use ment_os::{exit_qemu, exit_token, serial_print, serial_println, QemuExitCode};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = IdtBuilder::new();
+        idt.set_exception(Exception::DIVIDE_ERROR, test_divide_error_handler);
+        idt.build()
+    };
+}
+
+// Returning would retry the `div`, so the test ends here.
+extern "x86-interrupt" fn test_divide_error_handler(
+    _stack_frame: InterruptStackFrame)
+{
+    serial_println!("[ok]");
+    exit_qemu(exit_token(), QemuExitCode::Success);
+    ment_os::hlt_loop();
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("divide_error::div_by_zero_raises_de...\t");
+    TEST_IDT.load();
+    unsafe {
+        core::arch::asm!("div {divisor}", divisor = in(reg) 0u64,
+            inout("rax") 1u64 => _, inout("rdx") 0u64 => _);
+    }
+    serial_println!("[test did not fault]");
+    exit_qemu(exit_token(), QemuExitCode::Failed);
+    ment_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ment_os::test_panic_handler(info)
+}
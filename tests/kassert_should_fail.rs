@@ -0,0 +1,22 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use ment_os::panic::{self as kernel_panic, PanicBehavior};
+use ment_os::{exit_qemu, exit_token, kassert_eq, serial_print, serial_println, QemuExitCode};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    kernel_panic::set_behavior(PanicBehavior::QemuExit(QemuExitCode::Failed));
+    serial_print!("kassert_should_fail::kassert_eq_mismatch...\t");
+    ment_os::kassert::expect_failure();
+    kassert_eq!(2 + 2, 5);
+    serial_println!("[test did not fail]");
+    exit_qemu(exit_token(), QemuExitCode::Failed);
+    ment_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ment_os::test_panic_handler(info)
+}
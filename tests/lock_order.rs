@@ -0,0 +1,35 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use ment_os::sync::OrderedMutex;
+use ment_os::{exit_qemu, exit_token, serial_print, serial_println, QemuExitCode};
+
+static FIRST: OrderedMutex<()> = OrderedMutex::new("first", 1, ());
+static SECOND: OrderedMutex<()> = OrderedMutex::new("second", 2, ());
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("lock_order::opposite_order_panics...\t");
+    if !cfg!(debug_assertions) {
+        // Release builds do not track lock order.
+        serial_println!("[ok]");
+        exit_qemu(exit_token(), QemuExitCode::Success);
+    }
+    {
+        let _first = FIRST.lock();
+        let _second = SECOND.lock();
+    }
+    let _second = SECOND.lock();
+    let _first = FIRST.lock();
+    serial_println!("[test did not panic]");
+    exit_qemu(exit_token(), QemuExitCode::Failed);
+    ment_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(exit_token(), QemuExitCode::Success);
+    ment_os::hlt_loop();
+}